@@ -0,0 +1,24 @@
+CREATE TABLE consent_events(
+   id uuid PRIMARY KEY,
+   subscriber_id uuid NOT NULL
+      REFERENCES subscriptions (id),
+   event_type TEXT NOT NULL,
+   occurred_at timestamptz NOT NULL,
+   ip_address TEXT NULL,
+   user_agent TEXT NULL,
+   source TEXT NOT NULL,
+   consent_text_version TEXT NOT NULL
+);
+CREATE INDEX consent_events_subscriber_id_idx ON consent_events (subscriber_id);
+
+-- Consent records are evidence: they are never rewritten. Rows only go away
+-- together with their subscriber, when the subscriber data is erased.
+CREATE FUNCTION reject_consent_event_update() RETURNS trigger AS $$
+BEGIN
+   RAISE EXCEPTION 'consent_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER consent_events_append_only
+   BEFORE UPDATE ON consent_events
+   FOR EACH ROW EXECUTE FUNCTION reject_consent_event_update();
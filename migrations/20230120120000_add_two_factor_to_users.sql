@@ -0,0 +1,13 @@
+ALTER TABLE users ADD COLUMN totp_secret TEXT NULL;
+ALTER TABLE users ADD COLUMN totp_enabled BOOLEAN NOT NULL DEFAULT false;
+-- Codes are only accepted for later time steps than the last one used, so
+-- that an observed code cannot be replayed.
+ALTER TABLE users ADD COLUMN totp_last_used_step BIGINT NULL;
+
+CREATE TABLE recovery_codes(
+   id uuid PRIMARY KEY,
+   user_id uuid NOT NULL REFERENCES users (user_id),
+   code_hash TEXT NOT NULL,
+   used_at timestamptz NULL
+);
+CREATE INDEX recovery_codes_user_id_idx ON recovery_codes (user_id);
@@ -0,0 +1,5 @@
+-- Existing users keep the full access they had; accounts created from now
+-- on get the least privileged role unless told otherwise.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'admin';
+ALTER TABLE users ALTER COLUMN role SET DEFAULT 'viewer';
+ALTER TABLE users ADD CONSTRAINT users_role_check CHECK (role IN ('admin', 'editor', 'viewer'));
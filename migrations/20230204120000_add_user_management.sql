@@ -0,0 +1,26 @@
+-- Users created by hand before invitations existed have no email address
+-- and cannot reset their password until an admin adds one.
+ALTER TABLE users ADD COLUMN email TEXT NULL UNIQUE;
+ALTER TABLE users ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE users ADD COLUMN disabled_at TIMESTAMPTZ NULL;
+
+CREATE TABLE user_invitations(
+   id uuid NOT NULL,
+   PRIMARY KEY (id),
+   email TEXT NOT NULL,
+   role TEXT NOT NULL CHECK (role IN ('admin', 'editor', 'viewer')),
+   invited_by uuid NULL REFERENCES users (user_id) ON DELETE SET NULL,
+   created_at TIMESTAMPTZ NOT NULL,
+   expires_at TIMESTAMPTZ NOT NULL,
+   accepted_at TIMESTAMPTZ NULL
+);
+
+CREATE TABLE password_reset_tokens(
+   token_hash TEXT NOT NULL,
+   PRIMARY KEY (token_hash),
+   user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+   created_at TIMESTAMPTZ NOT NULL,
+   expires_at TIMESTAMPTZ NOT NULL,
+   used_at TIMESTAMPTZ NULL
+);
+CREATE INDEX password_reset_tokens_user_id_idx ON password_reset_tokens (user_id);
@@ -0,0 +1,5 @@
+-- `Foo@Example.COM` and `foo@example.com` reach the same mailbox, so they
+-- must not be two subscribers. Subscribers that only differ by the case of
+-- their email address have to be merged by hand before this can run.
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
+CREATE UNIQUE INDEX subscriptions_email_lower_key ON subscriptions (lower(email));
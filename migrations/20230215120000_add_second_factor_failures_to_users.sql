@@ -0,0 +1,3 @@
+-- Kept apart from `failed_login_attempts`, which a correct password resets:
+-- only a correct second factor (or an admin unlocking the account) resets it.
+ALTER TABLE users ADD COLUMN failed_second_factor_attempts INTEGER NOT NULL DEFAULT 0;
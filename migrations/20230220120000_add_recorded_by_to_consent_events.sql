@@ -0,0 +1,5 @@
+-- Admins confirm and import subscribers on their behalf: those events name
+-- the admin instead of carrying the admin's address and user agent as if
+-- they were the subscriber's. No foreign key, so that the events outlive
+-- the admin's account.
+ALTER TABLE consent_events ADD COLUMN recorded_by uuid NULL;
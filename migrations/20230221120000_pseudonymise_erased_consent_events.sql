@@ -0,0 +1,26 @@
+-- Erasing a subscriber must not erase the proof that they consented, nor
+-- that they asked to be forgotten. Their consent events are kept, detached
+-- from the subscription and stripped of the address and user agent, with a
+-- keyed hash of the email address in place of the subscriber: whoever
+-- brings the address up can still be shown the events, which otherwise
+-- name nobody. The erasure itself is recorded as an `erased` event.
+ALTER TABLE consent_events ADD COLUMN subscriber_hash TEXT NULL;
+ALTER TABLE consent_events ALTER COLUMN subscriber_id DROP NOT NULL;
+ALTER TABLE consent_events ADD CONSTRAINT consent_events_subscriber_check
+   CHECK (subscriber_id IS NOT NULL OR subscriber_hash IS NOT NULL);
+
+-- Pseudonymising the events on erasure is the only change they allow.
+CREATE OR REPLACE FUNCTION reject_consent_event_update() RETURNS trigger AS $$
+BEGIN
+   IF OLD.subscriber_id IS NOT NULL AND OLD.subscriber_hash IS NULL
+      AND NEW.subscriber_id IS NULL AND NEW.subscriber_hash IS NOT NULL
+      AND NEW.ip_address IS NULL AND NEW.user_agent IS NULL
+      AND (NEW.id, NEW.event_type, NEW.occurred_at, NEW.source, NEW.consent_text_version, NEW.recorded_by)
+         IS NOT DISTINCT FROM
+         (OLD.id, OLD.event_type, OLD.occurred_at, OLD.source, OLD.consent_text_version, OLD.recorded_by)
+   THEN
+      RETURN NEW;
+   END IF;
+   RAISE EXCEPTION 'consent_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
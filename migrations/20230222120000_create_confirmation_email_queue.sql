@@ -0,0 +1,14 @@
+-- Confirmation emails the worker sends, so that importing thousands of
+-- subscribers does not send thousands of emails within the request.
+-- Failed deliveries are retried later, up to a limit. The rows go with the
+-- subscriber, e.g. when they are erased.
+CREATE TABLE confirmation_email_queue(
+   subscription_token TEXT NOT NULL
+      REFERENCES subscription_tokens (subscription_token) ON DELETE CASCADE,
+   subscriber_id uuid NOT NULL
+      REFERENCES subscriptions (id) ON DELETE CASCADE,
+   enqueued_at timestamptz NOT NULL,
+   n_retries SMALLINT NOT NULL DEFAULT 0,
+   execute_after timestamptz NOT NULL,
+   PRIMARY KEY (subscription_token)
+);
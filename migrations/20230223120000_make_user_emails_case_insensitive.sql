@@ -0,0 +1,5 @@
+-- Like subscribers, users whose emails only differ by case are the same
+-- person. Users that only differ by the case of their email address have to
+-- be merged by hand before this can run.
+ALTER TABLE users DROP CONSTRAINT users_email_key;
+CREATE UNIQUE INDEX users_email_lower_key ON users (lower(email));
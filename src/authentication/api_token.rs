@@ -0,0 +1,141 @@
+use anyhow::Context;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{AuthError, Permission};
+
+
+/// Prefix of every token, so that leaked tokens are easy to spot in logs
+/// and by secret scanners.
+const TOKEN_PREFIX: &str = "z2p_";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ApiScope {
+    PublishNewsletters,
+    ReadSubscribers,
+}
+
+impl ApiScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::PublishNewsletters => "newsletters:publish",
+            ApiScope::ReadSubscribers => "subscribers:read",
+        }
+    }
+
+    pub fn permission(&self) -> Permission {
+        match self {
+            ApiScope::PublishNewsletters => Permission::PublishNewsletters,
+            ApiScope::ReadSubscribers => Permission::ReadSubscribers,
+        }
+    }
+}
+
+impl TryFrom<String> for ApiScope {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "newsletters:publish" => Ok(ApiScope::PublishNewsletters),
+            "subscribers:read" => Ok(ApiScope::ReadSubscribers),
+            other => Err(format!(
+                "`{}` is not a supported scope. Use either `newsletters:publish` or `subscribers:read`.",
+                other
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What a valid API token lets its bearer do. The scopes are enforced by
+/// `Principal::authorize`, together with the role of the owner.
+#[derive(Debug)]
+pub struct ApiTokenGrant {
+    pub user_id: Uuid,
+    pub scopes: Vec<ApiScope>,
+}
+
+pub fn generate_api_token() -> Secret<String> {
+    let mut rng = thread_rng();
+    let random: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(40)
+        .collect();
+    Secret::new(format!("{}{}", TOKEN_PREFIX, random))
+}
+
+/// Tokens are long random strings, so unlike passwords a fast hash is
+/// enough and lets us look them up by hash.
+pub fn hash_api_token(token: &Secret<String>) -> String {
+    hex::encode(Sha256::digest(token.expose_secret().as_bytes()))
+}
+
+#[tracing::instrument(
+    name = "Validate API token",
+    skip(token, pool)
+)]
+pub async fn validate_api_token(
+    token: Secret<String>,
+    pool: &PgPool,
+) -> Result<ApiTokenGrant, AuthError> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET last_used_at = now()
+        WHERE token_hash = $1
+            AND revoked_at IS NULL
+            AND (expires_at IS NULL OR expires_at > now())
+        RETURNING user_id, scopes
+        "#,
+        hash_api_token(&token),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to validate an API token")?
+    .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown, expired or revoked API token.")))?;
+
+    // Scopes that are no longer supported are ignored rather than failing the request.
+    let scopes = row.scopes
+        .into_iter()
+        .filter_map(|scope| ApiScope::try_from(scope).ok())
+        .collect();
+
+    Ok(ApiTokenGrant { user_id: row.user_id, scopes })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn scopes_round_trip_through_their_names() {
+        for scope in [ApiScope::PublishNewsletters, ApiScope::ReadSubscribers] {
+            assert_ok_eq!(ApiScope::try_from(scope.as_str().to_string()), scope);
+        }
+    }
+
+    #[test]
+    fn unknown_scopes_are_rejected() {
+        assert_err!(ApiScope::try_from("subscribers:write".to_string()));
+    }
+
+    #[test]
+    fn generated_tokens_are_distinct_and_prefixed() {
+        let first = generate_api_token();
+        let second = generate_api_token();
+
+        assert!(first.expose_secret().starts_with(TOKEN_PREFIX));
+        assert_ne!(hash_api_token(&first), hash_api_token(&second));
+    }
+}
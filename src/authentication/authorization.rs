@@ -0,0 +1,218 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::HttpMessage;
+use actix_web_lab::middleware::{from_fn, MiddlewareFn, Next};
+use anyhow::Context;
+use futures_util::future::LocalBoxFuture;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::middleware::auth_error;
+use crate::authentication::{ApiScope, AuthError};
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Role {
+    Admin,
+    Editor,
+    Viewer,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Permission {
+    ReadSubscribers,
+    ManageSubscribers,
+    PublishNewsletters,
+    ManageUsers,
+    /// Every user manages their own API tokens, but tokens cannot.
+    ManageApiTokens,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Editor => "editor",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    pub fn grants(&self, permission: Permission) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::Editor => matches!(
+                permission,
+                Permission::ReadSubscribers | Permission::PublishNewsletters | Permission::ManageApiTokens
+            ),
+            Role::Viewer => matches!(
+                permission,
+                Permission::ReadSubscribers | Permission::ManageApiTokens
+            ),
+        }
+    }
+}
+
+impl TryFrom<String> for Role {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "admin" => Ok(Role::Admin),
+            "editor" => Ok(Role::Editor),
+            "viewer" => Ok(Role::Viewer),
+            other => Err(format!(
+                "`{}` is not a supported role. Use either `admin`, `editor` or `viewer`.",
+                other
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::fmt::Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Permission::ReadSubscribers => "read subscribers",
+            Permission::ManageSubscribers => "manage subscribers",
+            Permission::PublishNewsletters => "publish newsletters",
+            Permission::ManageUsers => "manage users",
+            Permission::ManageApiTokens => "manage API tokens",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Who is making the request, as established by authentication.
+#[derive(Clone, Debug)]
+pub struct Principal {
+    pub user_id: Uuid,
+    pub role: Role,
+    /// Set when the request authenticated with an API token, which then
+    /// narrows what the role allows down to the token's scopes.
+    pub token_scopes: Option<Vec<ApiScope>>,
+}
+
+impl Principal {
+    /// The single place where permissions are checked.
+    pub fn authorize(&self, permission: Permission) -> Result<(), AuthError> {
+        let scoped = self.token_scopes
+            .as_ref()
+            .is_none_or(|scopes| scopes.iter().any(|scope| scope.permission() == permission));
+        if self.role.grants(permission) && scoped {
+            Ok(())
+        } else {
+            Err(AuthError::Forbidden(permission))
+        }
+    }
+}
+
+/// Looks up the current role of an authenticated user. Roles are not cached
+/// in sessions or tokens, so changes apply to the very next request, and
+/// disabled users are no longer authenticated.
+#[tracing::instrument(
+    name = "Load principal",
+    skip(token_scopes, pool)
+)]
+pub async fn load_principal(
+    user_id: Uuid,
+    token_scopes: Option<Vec<ApiScope>>,
+    pool: &PgPool,
+) -> Result<Principal, AuthError> {
+    let role = sqlx::query!(
+        r#"SELECT role FROM users WHERE user_id = $1 AND disabled_at IS NULL"#,
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the role of the user")?
+    .ok_or(AuthError::Unauthenticated)?
+    .role;
+    let role = Role::try_from(role)
+        .map_err(|e| AuthError::UnexpectedError(anyhow::anyhow!(e)))?;
+
+    Ok(Principal { user_id, role, token_scopes })
+}
+
+/// Route guard rejecting requests whose `Principal` lacks `permission`:
+///
+/// ```ignore
+/// web::get().to(list_subscribers).wrap(require_permission(Permission::ReadSubscribers))
+/// ```
+///
+/// It has to run inside a middleware that authenticates the request, such
+/// as `reject_anonymous_users`; without a `Principal` the request is
+/// rejected as unauthenticated.
+#[allow(clippy::type_complexity)]
+pub fn require_permission<B: MessageBody + 'static>(
+    permission: Permission,
+) -> MiddlewareFn<
+    impl Fn(ServiceRequest, Next<B>) -> LocalBoxFuture<'static, Result<ServiceResponse<B>, actix_web::Error>>,
+> {
+    from_fn(move |req: ServiceRequest, next: Next<B>| -> LocalBoxFuture<'static, _> {
+        Box::pin(async move {
+            let principal = req.extensions().get::<Principal>().cloned();
+            match principal {
+                Some(principal) => principal.authorize(permission).map_err(auth_error)?,
+                None => return Err(auth_error(AuthError::Unauthenticated)),
+            }
+            next.call(req).await
+        })
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::{assert_err, assert_ok};
+
+    fn principal(role: Role, token_scopes: Option<Vec<ApiScope>>) -> Principal {
+        Principal { user_id: Uuid::new_v4(), role, token_scopes }
+    }
+
+    #[test]
+    fn viewers_can_only_read() {
+        let viewer = principal(Role::Viewer, None);
+
+        assert_ok!(viewer.authorize(Permission::ReadSubscribers));
+        assert_err!(viewer.authorize(Permission::PublishNewsletters));
+        assert_err!(viewer.authorize(Permission::ManageSubscribers));
+    }
+
+    #[test]
+    fn editors_can_publish_but_not_manage_users() {
+        let editor = principal(Role::Editor, None);
+
+        assert_ok!(editor.authorize(Permission::PublishNewsletters));
+        assert_err!(editor.authorize(Permission::ManageUsers));
+    }
+
+    #[test]
+    fn tokens_are_limited_to_their_scopes() {
+        let token = principal(Role::Admin, Some(vec![ApiScope::PublishNewsletters]));
+
+        assert_ok!(token.authorize(Permission::PublishNewsletters));
+        assert_err!(token.authorize(Permission::ReadSubscribers));
+        assert_err!(token.authorize(Permission::ManageApiTokens));
+    }
+
+    #[test]
+    fn tokens_cannot_exceed_the_role_of_their_owner() {
+        let token = principal(Role::Viewer, Some(vec![ApiScope::PublishNewsletters]));
+
+        assert_err!(token.authorize(Permission::PublishNewsletters));
+    }
+
+    #[test]
+    fn roles_round_trip_through_their_names() {
+        for role in [Role::Admin, Role::Editor, Role::Viewer] {
+            assert_eq!(Role::try_from(role.as_str().to_string()), Ok(role));
+        }
+        assert_err!(Role::try_from("owner".to_string()));
+    }
+}
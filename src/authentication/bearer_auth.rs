@@ -0,0 +1,30 @@
+use actix_web::http::header::HeaderMap;
+use anyhow::Context;
+use secrecy::Secret;
+
+
+/// Whether the request authenticates with `Authorization: Bearer`, in which
+/// case `bearer_token` rather than `basic_authentication` applies.
+pub fn has_bearer_token(headers: &HeaderMap) -> bool {
+    headers
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("Bearer "))
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Result<Secret<String>, anyhow::Error> {
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing.")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF-8 string.")?;
+    let token = header_value
+        .strip_prefix("Bearer ")
+        .context("The authorization scheme was not 'Bearer'.")?
+        .trim();
+    if token.is_empty() {
+        anyhow::bail!("A token must be provided in 'Bearer' auth.");
+    }
+
+    Ok(Secret::new(token.to_string()))
+}
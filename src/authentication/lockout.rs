@@ -0,0 +1,388 @@
+use std::sync::Arc;
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{validate_credentials, verify_second_factor, AuthError, Credentials, PasswordHashing};
+use crate::configuration::LoginProtectionSettings;
+use crate::rate_limit::{self, RateLimiter};
+
+
+/// Guards `validate_credentials` against brute-forcing: failures are counted
+/// per account (in the database, so that admins can see and clear lockouts)
+/// and per client address (in the rate limiter), every failure is answered
+/// a little later than the previous one, and either counter reaching its
+/// maximum rejects further attempts for a while.
+pub struct LoginThrottle {
+    settings: LoginProtectionSettings,
+    password_hashing: PasswordHashing,
+    failures_per_ip: Arc<RateLimiter>,
+}
+
+impl LoginThrottle {
+    pub fn new(
+        settings: LoginProtectionSettings,
+        password_hashing: PasswordHashing,
+        failures_per_ip: Arc<RateLimiter>,
+    ) -> Self {
+        Self { settings, password_hashing, failures_per_ip }
+    }
+
+    pub fn client_ip(&self, request: &HttpRequest) -> Option<String> {
+        rate_limit::client_ip(request, self.settings.trusted_proxy_hops)
+    }
+
+    #[tracing::instrument(
+        name = "Validate credentials with brute-force protection",
+        skip(self, credentials, pool)
+    )]
+    pub async fn validate_credentials(
+        &self,
+        credentials: Credentials,
+        client_ip: Option<&str>,
+        pool: &PgPool,
+    ) -> Result<Uuid, AuthError> {
+        let ip_key = client_ip.map(|ip| format!("login_failures:ip:{}", ip));
+        if let Some(ip_key) = &ip_key {
+            let failures = self.ip_failures(ip_key).await;
+            if failures >= self.settings.max_failed_per_ip as u64 {
+                return Err(AuthError::TooManyAttempts(anyhow::anyhow!(
+                    "Too many failed attempts from this address."
+                )));
+            }
+        }
+
+        let username = credentials.username.clone();
+        let locked_until = get_locked_until(&username, pool)
+            .await?
+            .filter(|locked_until| *locked_until > Utc::now());
+
+        // Locked accounts are answered like a wrong password, once the
+        // password has been checked all the same, so that lockouts do not
+        // reveal which usernames exist.
+        let outcome = validate_credentials(credentials, &self.password_hashing, pool).await;
+        let (error, account_failures) = match (outcome, locked_until) {
+            (Ok(user_id), None) => {
+                reset_failed_attempts(user_id, pool).await?;
+                return Ok(user_id);
+            }
+            (Ok(_) | Err(AuthError::InvalidCredentials(_)), Some(locked_until)) => {
+                (anyhow::anyhow!("The account is locked until {}.", locked_until), 0)
+            }
+            (Err(AuthError::InvalidCredentials(e)), None) => {
+                (e, self.record_account_failure(&username, client_ip, pool).await?)
+            }
+            (Err(e), _) => return Err(e),
+        };
+
+        let ip_failures = match &ip_key {
+            Some(ip_key) => self.record_ip_failure(ip_key, client_ip).await,
+            None => 0,
+        };
+        // Unknown usernames without a known address still count as one failure.
+        let failures = account_failures.max(ip_failures).max(1);
+        tokio::time::sleep(self.settings.delay_after(failures)).await;
+        Err(AuthError::InvalidCredentials(error))
+    }
+
+    /// Checks the second factor of a user who got past the password. Wrong
+    /// codes are counted in the database rather than in the session, which
+    /// the client holds and could replay to start over, and lock the
+    /// account once they reach `max_failed_attempts`.
+    #[tracing::instrument(
+        name = "Verify second factor with brute-force protection",
+        skip(self, code, pool)
+    )]
+    pub async fn verify_second_factor(
+        &self,
+        user_id: Uuid,
+        code: &str,
+        pool: &PgPool,
+    ) -> Result<(), AuthError> {
+        let locked_until = get_locked_until_by_user_id(user_id, pool)
+            .await?
+            .filter(|locked_until| *locked_until > Utc::now());
+        if let Some(locked_until) = locked_until {
+            return Err(AuthError::TooManyAttempts(anyhow::anyhow!(
+                "The account is locked until {}.", locked_until
+            )));
+        }
+
+        if verify_second_factor(user_id, code, pool).await? {
+            reset_failed_second_factors(user_id, pool).await?;
+            return Ok(());
+        }
+
+        let failures = self.record_second_factor_failure(user_id, pool).await?;
+        if failures >= self.settings.max_failed_attempts as u64 {
+            return Err(AuthError::TooManyAttempts(anyhow::anyhow!(
+                "Too many wrong two-factor codes."
+            )));
+        }
+        tokio::time::sleep(self.settings.delay_after(failures)).await;
+        Err(AuthError::InvalidCredentials(anyhow::anyhow!("Wrong two-factor code.")))
+    }
+
+    /// Rate limiter outages must not lock everybody out, so they count as no failures.
+    async fn ip_failures(&self, ip_key: &str) -> u64 {
+        self.failures_per_ip.count(ip_key).await.unwrap_or_else(|e| {
+            tracing::warn!(error.cause_chain = ?e, "Failed to read the failed login attempts of an address");
+            0
+        })
+    }
+
+    async fn record_ip_failure(&self, ip_key: &str, client_ip: Option<&str>) -> u64 {
+        let failures = match self.failures_per_ip.increment(ip_key, self.settings.ip_window()).await {
+            Ok(failures) => failures,
+            Err(e) => {
+                tracing::warn!(error.cause_chain = ?e, "Failed to record a failed login attempt of an address");
+                return 0;
+            }
+        };
+        if failures == self.settings.max_failed_per_ip as u64 {
+            tracing::warn!(
+                target: "security",
+                security_event = "address_blocked",
+                client_ip = client_ip,
+                failed_attempts = failures,
+                blocked_for_seconds = self.settings.ip_window_seconds,
+                "Blocked an address after too many failed login attempts"
+            );
+        }
+        failures
+    }
+
+    /// Returns the consecutive failures of the account, `0` for unknown usernames.
+    async fn record_account_failure(
+        &self,
+        username: &str,
+        client_ip: Option<&str>,
+        pool: &PgPool,
+    ) -> Result<u64, anyhow::Error> {
+        let row = sqlx::query!(
+            r#"
+            UPDATE users
+            SET failed_login_attempts = failed_login_attempts + 1
+            WHERE username = $1
+            RETURNING user_id, failed_login_attempts
+            "#,
+            username,
+        )
+        .fetch_optional(pool)
+        .await
+        .context("Failed to record a failed login attempt")?;
+        let (user_id, failures) = match row {
+            Some(row) => (row.user_id, row.failed_login_attempts as u64),
+            None => return Ok(0),
+        };
+
+        if failures >= self.settings.max_failed_attempts as u64 {
+            let locked_until = Utc::now() + chrono::Duration::from_std(self.settings.lockout())
+                .context("The lockout duration is out of range")?;
+            // The counter starts over so that the account gets the full
+            // allowance again once the lockout has expired.
+            sqlx::query!(
+                r#"UPDATE users SET failed_login_attempts = 0, locked_until = $2 WHERE user_id = $1"#,
+                user_id,
+                locked_until,
+            )
+            .execute(pool)
+            .await
+            .context("Failed to lock an account")?;
+            tracing::warn!(
+                target: "security",
+                security_event = "account_locked",
+                %user_id,
+                username = username,
+                client_ip = client_ip,
+                failed_attempts = failures,
+                %locked_until,
+                "Locked an account after too many failed login attempts"
+            );
+        }
+
+        Ok(failures)
+    }
+
+    /// Returns the consecutive wrong second factors of the account, which
+    /// start over once they have locked it.
+    async fn record_second_factor_failure(
+        &self,
+        user_id: Uuid,
+        pool: &PgPool,
+    ) -> Result<u64, anyhow::Error> {
+        let failures = sqlx::query!(
+            r#"
+            UPDATE users
+            SET failed_second_factor_attempts = failed_second_factor_attempts + 1
+            WHERE user_id = $1
+            RETURNING failed_second_factor_attempts
+            "#,
+            user_id,
+        )
+        .fetch_one(pool)
+        .await
+        .context("Failed to record a wrong second factor")?
+        .failed_second_factor_attempts as u64;
+
+        if failures >= self.settings.max_failed_attempts as u64 {
+            let locked_until = Utc::now() + chrono::Duration::from_std(self.settings.lockout())
+                .context("The lockout duration is out of range")?;
+            sqlx::query!(
+                r#"UPDATE users SET failed_second_factor_attempts = 0, locked_until = $2 WHERE user_id = $1"#,
+                user_id,
+                locked_until,
+            )
+            .execute(pool)
+            .await
+            .context("Failed to lock an account")?;
+            tracing::warn!(
+                target: "security",
+                security_event = "account_locked",
+                %user_id,
+                failed_attempts = failures,
+                %locked_until,
+                "Locked an account after too many wrong two-factor codes"
+            );
+        }
+
+        Ok(failures)
+    }
+}
+
+#[tracing::instrument(
+    name = "Get account lock",
+    skip(username, pool)
+)]
+async fn get_locked_until(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+    let locked_until = sqlx::query!(
+        r#"SELECT locked_until FROM users WHERE username = $1"#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the account lock")?
+    .and_then(|row| row.locked_until);
+
+    Ok(locked_until)
+}
+
+#[tracing::instrument(
+    name = "Get account lock by user id",
+    skip(pool)
+)]
+async fn get_locked_until_by_user_id(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+    let locked_until = sqlx::query!(
+        r#"SELECT locked_until FROM users WHERE user_id = $1"#,
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the account lock")?
+    .and_then(|row| row.locked_until);
+
+    Ok(locked_until)
+}
+
+async fn reset_failed_attempts(user_id: Uuid, pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET failed_login_attempts = 0, locked_until = NULL
+        WHERE user_id = $1 AND (failed_login_attempts > 0 OR locked_until IS NOT NULL)
+        "#,
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to reset the failed login attempts")?;
+
+    Ok(())
+}
+
+async fn reset_failed_second_factors(user_id: Uuid, pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET failed_second_factor_attempts = 0
+        WHERE user_id = $1 AND failed_second_factor_attempts > 0
+        "#,
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to reset the wrong second factors")?;
+
+    Ok(())
+}
+
+/// Lifts the lock of an account and forgets its failed attempts. Returns
+/// `false` if there is no such user.
+#[tracing::instrument(
+    name = "Unlock account",
+    skip(pool)
+)]
+pub async fn unlock_account(user_id: Uuid, pool: &PgPool) -> Result<bool, sqlx::Error> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE users
+        SET failed_login_attempts = 0, failed_second_factor_attempts = 0, locked_until = NULL
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(updated > 0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn settings() -> LoginProtectionSettings {
+        LoginProtectionSettings {
+            max_failed_attempts: 5,
+            lockout_seconds: 900,
+            max_failed_per_ip: 50,
+            ip_window_seconds: 900,
+            delay_step_milliseconds: 250,
+            max_delay_milliseconds: 4000,
+            trusted_proxy_hops: 0,
+        }
+    }
+
+    #[test]
+    fn the_delay_doubles_with_every_failure() {
+        let settings = settings();
+
+        assert_eq!(settings.delay_after(1), Duration::from_millis(250));
+        assert_eq!(settings.delay_after(2), Duration::from_millis(500));
+        assert_eq!(settings.delay_after(3), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn the_delay_is_capped() {
+        let settings = settings();
+
+        assert_eq!(settings.delay_after(6), Duration::from_millis(4000));
+        assert_eq!(settings.delay_after(u64::MAX), Duration::from_millis(4000));
+    }
+
+    #[test]
+    fn there_is_no_delay_without_failures() {
+        assert_eq!(settings().delay_after(0), Duration::ZERO);
+    }
+}
@@ -0,0 +1,154 @@
+use std::ops::Deref;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{HeaderValue, LOCATION, WWW_AUTHENTICATE};
+use actix_web::http::StatusCode;
+use actix_web::{web, FromRequest, HttpMessage, HttpResponse};
+use actix_web_lab::middleware::Next;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{
+    basic_authentication, bearer_token, has_bearer_token, is_two_factor_enabled, load_principal,
+    validate_api_token, AuthError, LoginThrottle,
+};
+use crate::session_state::TypedSession;
+
+
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Authenticates admin API requests and makes the `UserId` and `Principal`
+/// available to downstream handlers via `web::ReqData`.
+///
+/// A logged-in session, an API token (`Authorization: Bearer`) or HTTP Basic
+/// credentials are accepted. What the caller may do is up to the
+/// `require_permission` guard of each route.
+pub async fn reject_anonymous_users(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+    let pool = {
+        let (http_request, payload) = req.parts_mut();
+        web::Data::<PgPool>::from_request(http_request, payload).await
+    }?;
+
+    let session_user_id = session
+        .get_user_id()
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let (user_id, token_scopes) = match session_user_id {
+        Some(user_id) => (user_id, None),
+        None if has_bearer_token(req.headers()) => {
+            let token = bearer_token(req.headers()).map_err(unauthorized)?;
+            let grant = validate_api_token(token, &pool).await.map_err(auth_error)?;
+            (grant.user_id, Some(grant.scopes))
+        },
+        None => {
+            let login_throttle = {
+                let (http_request, payload) = req.parts_mut();
+                web::Data::<LoginThrottle>::from_request(http_request, payload).await
+            }?;
+            let credentials = basic_authentication(req.headers()).map_err(unauthorized)?;
+            let client_ip = login_throttle.client_ip(req.request());
+            let user_id = login_throttle
+                .validate_credentials(credentials, client_ip.as_deref(), &pool)
+                .await
+                .map_err(auth_error)?;
+            // A password alone must not get around a second factor.
+            let two_factor_enabled = is_two_factor_enabled(user_id, &pool)
+                .await
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+            if two_factor_enabled {
+                return Err(unauthorized(anyhow::anyhow!(
+                    "Basic authentication is disabled for accounts with two-factor authentication."
+                )));
+            }
+            (user_id, None)
+        },
+    };
+
+    let principal = load_principal(user_id, token_scopes, &pool)
+        .await
+        .map_err(auth_error)?;
+    req.extensions_mut().insert(UserId(user_id));
+    req.extensions_mut().insert(principal);
+    next.call(req).await
+}
+
+/// Makes the `UserId` of a logged-in session available to downstream
+/// handlers via `web::ReqData<UserId>`, redirecting to `/login` otherwise.
+/// Sessions still waiting for a second factor are not logged in, and the
+/// sessions of disabled users are ended.
+pub async fn require_login(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+    let pool = {
+        let (http_request, payload) = req.parts_mut();
+        web::Data::<PgPool>::from_request(http_request, payload).await
+    }?;
+
+    let user_id = match session.get_user_id().map_err(actix_web::error::ErrorInternalServerError)? {
+        Some(user_id) => user_id,
+        None => return Err(redirect_to_login(anyhow::anyhow!("The user has not logged in"))),
+    };
+    match load_principal(user_id, None, &pool).await {
+        Ok(_) => {
+            req.extensions_mut().insert(UserId(user_id));
+            next.call(req).await
+        },
+        Err(AuthError::UnexpectedError(e)) => Err(actix_web::error::ErrorInternalServerError(e)),
+        Err(e) => {
+            session.log_out();
+            Err(redirect_to_login(e.into()))
+        },
+    }
+}
+
+fn redirect_to_login(e: anyhow::Error) -> actix_web::Error {
+    let response = HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/login"))
+        .finish();
+    InternalError::from_response(e, response).into()
+}
+
+pub(crate) fn auth_error(e: AuthError) -> actix_web::Error {
+    match e {
+        AuthError::Unauthenticated => unauthorized(anyhow::anyhow!(e)),
+        AuthError::InvalidCredentials(e) => unauthorized(e),
+        AuthError::Forbidden(_) => actix_web::error::ErrorForbidden(e),
+        AuthError::TooManyAttempts(e) => actix_web::error::ErrorTooManyRequests(e),
+        AuthError::UnexpectedError(e) => actix_web::error::ErrorInternalServerError(e),
+    }
+}
+
+fn unauthorized(e: anyhow::Error) -> actix_web::Error {
+    let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+    response
+        .headers_mut()
+        .insert(WWW_AUTHENTICATE, HeaderValue::from_static(r#"Basic realm="admin""#));
+    InternalError::from_response(e, response).into()
+}
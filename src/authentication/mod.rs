@@ -0,0 +1,28 @@
+mod api_token;
+mod authorization;
+mod basic_auth;
+mod bearer_auth;
+mod lockout;
+mod middleware;
+mod password;
+pub mod totp;
+mod two_factor;
+mod users;
+
+pub use api_token::{
+    generate_api_token, hash_api_token, validate_api_token, ApiScope, ApiTokenGrant,
+};
+pub use authorization::{load_principal, require_permission, Permission, Principal, Role};
+pub use basic_auth::basic_authentication;
+pub use bearer_auth::{bearer_token, has_bearer_token};
+pub use lockout::{unlock_account, LoginThrottle};
+pub use middleware::{reject_anonymous_users, require_login, UserId};
+pub use password::{
+    change_password, validate_credentials, validate_new_password, AuthError, Credentials,
+    PasswordHashing,
+};
+pub use two_factor::{
+    confirm_two_factor_enrolment, disable_two_factor, is_two_factor_enabled,
+    start_two_factor_enrolment, verify_second_factor,
+};
+pub use users::{create_initial_admin, create_user, CreateUserError};
@@ -0,0 +1,309 @@
+use crate::telemetry::spawn_blocking_with_tracing;
+use anyhow::Context;
+use secrecy::{Secret, ExposeSecret};
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::Permission;
+use crate::configuration::PasswordHashingSettings;
+
+
+const MIN_PASSWORD_LENGTH: usize = 12;
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    /// The request carried no credentials, or they belong to no user anymore.
+    #[error("Authentication is required.")]
+    Unauthenticated,
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] anyhow::Error),
+    /// The user is known but their role (or API token) does not allow this.
+    #[error("You are not allowed to {0}.")]
+    Forbidden(Permission),
+    #[error("Too many failed attempts, try again later.")]
+    TooManyAttempts(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+
+/// Hashes passwords with the configured Argon2id parameters.
+#[derive(Clone, Debug)]
+pub struct PasswordHashing {
+    params: Params,
+    /// Unknown usernames are checked against this hash, so that they take
+    /// as long as known ones.
+    dummy_hash: Secret<String>,
+}
+
+impl PasswordHashing {
+    pub fn new(settings: &PasswordHashingSettings) -> Result<Self, anyhow::Error> {
+        let params = Params::new(settings.memory_kib, settings.iterations, settings.parallelism, None)
+            .map_err(|e| anyhow::anyhow!("Invalid password hashing parameters: {}", e))?;
+        let mut hashing = Self { params, dummy_hash: Secret::new(String::new()) };
+        hashing.dummy_hash = hashing.hash(Secret::new(Uuid::new_v4().to_string()))?;
+        Ok(hashing)
+    }
+
+    pub fn hash(&self, password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let password_hash = self.argon2()
+            .hash_password(password.expose_secret().as_bytes(), &salt)?
+            .to_string();
+
+        Ok(Secret::new(password_hash))
+    }
+
+    /// Whether a stored hash was computed with another algorithm, version
+    /// or parameters than new hashes are.
+    pub fn is_outdated(&self, password_hash: &PasswordHash) -> bool {
+        let params = match Params::try_from(password_hash) {
+            Ok(params) => params,
+            Err(_) => return true,
+        };
+        password_hash.algorithm != Algorithm::Argon2id.ident()
+            || password_hash.version != Some(Version::V0x13.into())
+            || params.m_cost() != self.params.m_cost()
+            || params.t_cost() != self.params.t_cost()
+            || params.p_cost() != self.params.p_cost()
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+}
+
+/// Checks a username and password. Passwords stored with outdated
+/// parameters are rehashed with the current ones once they matched.
+#[tracing::instrument(
+name = "Validate credentials",
+skip(credentials, password_hashing, pool)
+)]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    password_hashing: &PasswordHashing,
+    pool: &PgPool
+) -> Result<uuid::Uuid, AuthError> {
+    let mut user_id = None;
+    let mut expected_password_hash = password_hashing.dummy_hash.clone();
+
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, pool).await?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    let password_hashing = password_hashing.clone();
+    let (outdated_hash, upgraded_hash) = spawn_blocking_with_tracing(move || {
+        verify_password_hash(&expected_password_hash, &credentials.password)?;
+        let upgraded_hash = rehash_if_outdated(&password_hashing, &expected_password_hash, credentials.password);
+        Ok::<_, AuthError>((expected_password_hash, upgraded_hash))
+    })
+        .await
+        .context("Failed to spawn blocking task.")??;
+
+    let user_id = user_id.ok_or_else(||
+        AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username."))
+    )?;
+    if let Some(upgraded_hash) = upgraded_hash {
+        // Failing to upgrade must not fail the login; it is retried next time.
+        if let Err(e) = store_upgraded_hash(user_id, &outdated_hash, &upgraded_hash, pool).await {
+            tracing::warn!(error.cause_chain = ?e, "Failed to store the upgraded password hash");
+        }
+    }
+
+    Ok(user_id)
+}
+
+fn rehash_if_outdated(
+    password_hashing: &PasswordHashing,
+    password_hash: &Secret<String>,
+    password: Secret<String>,
+) -> Option<Secret<String>> {
+    let parsed = PasswordHash::new(password_hash.expose_secret()).ok()?;
+    if !password_hashing.is_outdated(&parsed) {
+        return None;
+    }
+    password_hashing.hash(password)
+        .map_err(|e| tracing::warn!(error.cause_chain = ?e, "Failed to rehash an outdated password hash"))
+        .ok()
+}
+
+/// Only replaces the hash that was verified, in case the password was
+/// changed in the meantime.
+#[tracing::instrument(
+name = "Store upgraded password hash",
+skip(outdated_hash, upgraded_hash, pool)
+)]
+async fn store_upgraded_hash(
+    user_id: Uuid,
+    outdated_hash: &Secret<String>,
+    upgraded_hash: &Secret<String>,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE users SET password_hash = $3 WHERE user_id = $1 AND password_hash = $2"#,
+        user_id,
+        outdated_hash.expose_secret(),
+        upgraded_hash.expose_secret(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the upgraded password hash")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(
+name = "Get stored credentials",
+skip(username, pool)
+)]
+async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<(uuid::Uuid, Secret<String>)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, password_hash
+        FROM users
+        WHERE username = $1 AND disabled_at IS NULL
+        "#,
+        username,
+    )
+        .fetch_optional(pool)
+        .await
+        .context("Failed to perform a query to retrieve stored credentials")?
+        .map(|row| (row.user_id, Secret::new(row.password_hash))); // Why Secret?
+
+    Ok(row)
+}
+
+#[tracing::instrument(
+name = "Verify password hash",
+skip(expected_password_hash, password_candidate),
+)]
+fn verify_password_hash(
+    expected_password_hash: &Secret<String>,
+    password_candidate: &Secret<String>,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .context("Failed to parse hash in PHC string format")?;
+
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash
+        )
+        .context("Invalid password")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+/// Checks a password chosen by a user, returning why it is not acceptable.
+pub fn validate_new_password(password: &Secret<String>) -> Result<(), String> {
+    let length = password.expose_secret().chars().count();
+    if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&length) {
+        return Err(format!(
+            "The password must be between {} and {} characters long",
+            MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+/// Replaces the password of a user, lifting any lockout as it is no longer
+/// the password that was being guessed.
+#[tracing::instrument(
+    name = "Change password",
+    skip(password, password_hashing, pool)
+)]
+pub async fn change_password(
+    user_id: Uuid,
+    password: Secret<String>,
+    password_hashing: &PasswordHashing,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let password_hashing = password_hashing.clone();
+    let password_hash = spawn_blocking_with_tracing(move || password_hashing.hash(password))
+        .await?
+        .context("Failed to hash password")?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $2, failed_login_attempts = 0, locked_until = NULL
+        WHERE user_id = $1
+        "#,
+        user_id,
+        password_hash.expose_secret(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to change the password of the user")?;
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn short_passwords_are_rejected() {
+        assert_err!(validate_new_password(&Secret::new("a".repeat(11))));
+        assert_ok!(validate_new_password(&Secret::new("a".repeat(12))));
+    }
+
+    #[test]
+    fn overly_long_passwords_are_rejected() {
+        assert_err!(validate_new_password(&Secret::new("a".repeat(129))));
+    }
+
+    fn password_hashing(memory_kib: u32) -> PasswordHashing {
+        PasswordHashing::new(&PasswordHashingSettings { memory_kib, iterations: 1, parallelism: 1 }).unwrap()
+    }
+
+    #[test]
+    fn computed_hashes_verify_and_are_current() {
+        let password_hashing = password_hashing(1024);
+        let password = Secret::new("correct horse battery staple".to_string());
+        let hash = password_hashing.hash(password.clone()).unwrap();
+
+        assert_ok!(verify_password_hash(&hash, &password));
+        assert!(!password_hashing.is_outdated(&PasswordHash::new(hash.expose_secret()).unwrap()));
+    }
+
+    #[test]
+    fn hashes_with_other_parameters_are_outdated() {
+        let hash = password_hashing(1024).hash(Secret::new("password".to_string())).unwrap();
+
+        assert!(password_hashing(2048).is_outdated(&PasswordHash::new(hash.expose_secret()).unwrap()));
+    }
+
+    #[test]
+    fn hashes_with_other_algorithms_are_outdated() {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let hash = Argon2::new(Algorithm::Argon2i, Version::V0x13, Params::new(1024, 1, 1, None).unwrap())
+            .hash_password(b"password", &salt)
+            .unwrap()
+            .to_string();
+
+        assert!(password_hashing(1024).is_outdated(&PasswordHash::new(&hash).unwrap()));
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected() {
+        let settings = PasswordHashingSettings { memory_kib: 1, iterations: 0, parallelism: 1 };
+
+        assert_err!(PasswordHashing::new(&settings));
+    }
+}
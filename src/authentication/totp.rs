@@ -0,0 +1,113 @@
+//! Time-based one-time passwords (RFC 6238) with the parameters every
+//! authenticator app supports: HMAC-SHA1, 6 digits, 30 second steps.
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use secrecy::{ExposeSecret, Secret};
+use sha1::Sha1;
+
+
+pub const STEP_SECONDS: u64 = 30;
+const DIGITS: u32 = 6;
+const SECRET_BYTES: usize = 20;
+/// Steps either side of the current one that are still accepted, to allow
+/// for clock drift and slow typing.
+const ALLOWED_DRIFT: u64 = 1;
+
+const BASE32: base32::Alphabet = base32::Alphabet::RFC4648 { padding: false };
+
+/// A fresh shared secret, base32-encoded as authenticator apps expect it.
+pub fn generate_secret() -> Secret<String> {
+    let mut bytes = [0u8; SECRET_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    Secret::new(base32::encode(BASE32, &bytes))
+}
+
+/// The `otpauth://` URI authenticator apps enrol from, usually shown as a QR code.
+pub fn otpauth_uri(issuer: &str, account_name: &str, secret: &Secret<String>) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}",
+        issuer = urlencoding::encode(issuer),
+        account = urlencoding::encode(account_name),
+        secret = secret.expose_secret(),
+    )
+}
+
+pub fn step_at(unix_time: u64) -> u64 {
+    unix_time / STEP_SECONDS
+}
+
+/// The code for a time step, `None` if the secret is not valid base32.
+pub fn code_at_step(secret: &Secret<String>, step: u64) -> Option<String> {
+    let key = base32::decode(BASE32, secret.expose_secret())?;
+    let mut mac = Hmac::<Sha1>::new_from_slice(&key).ok()?;
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    // Dynamic truncation, RFC 4226 section 5.3.
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    Some(format!("{:0width$}", binary % 10u32.pow(DIGITS), width = DIGITS as usize))
+}
+
+/// Returns the time step `code` belongs to if it is valid around `unix_time`.
+pub fn verify_at(secret: &Secret<String>, code: &str, unix_time: u64) -> Option<u64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+    let current = step_at(unix_time);
+    (current.saturating_sub(ALLOWED_DRIFT)..=current + ALLOWED_DRIFT)
+        .find(|step| code_at_step(secret, *step).as_deref() == Some(code))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The SHA1 seed of RFC 6238, appendix B.
+    fn rfc_secret() -> Secret<String> {
+        Secret::new(base32::encode(BASE32, b"12345678901234567890"))
+    }
+
+    #[test]
+    fn codes_match_the_rfc_test_vectors() {
+        let secret = rfc_secret();
+
+        for (unix_time, code) in [(59, "287082"), (1111111109, "081804"), (1234567890, "005924")] {
+            assert_eq!(code_at_step(&secret, step_at(unix_time)).unwrap(), code);
+        }
+    }
+
+    #[test]
+    fn codes_from_adjacent_steps_are_accepted() {
+        let secret = rfc_secret();
+        let now = 1234567890;
+        let previous = code_at_step(&secret, step_at(now) - 1).unwrap();
+
+        assert_eq!(verify_at(&secret, &previous, now), Some(step_at(now) - 1));
+    }
+
+    #[test]
+    fn codes_from_distant_steps_are_rejected() {
+        let secret = rfc_secret();
+        let now = 1234567890;
+        let stale = code_at_step(&secret, step_at(now) - 2).unwrap();
+
+        assert_eq!(verify_at(&secret, &stale, now), None);
+    }
+
+    #[test]
+    fn generated_secrets_are_usable() {
+        let secret = generate_secret();
+
+        assert!(code_at_step(&secret, 1).is_some());
+        assert!(otpauth_uri("zero2prod", "ursula", &secret)
+            .starts_with("otpauth://totp/zero2prod:ursula?secret="));
+    }
+}
@@ -0,0 +1,297 @@
+use anyhow::Context;
+use chrono::Utc;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::authentication::totp;
+
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+#[tracing::instrument(
+    name = "Check whether two-factor authentication is enabled",
+    skip(pool)
+)]
+pub async fn is_two_factor_enabled(user_id: Uuid, pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let enabled = sqlx::query!(
+        r#"SELECT totp_enabled FROM users WHERE user_id = $1"#,
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to check whether two-factor authentication is enabled")?
+    .is_some_and(|row| row.totp_enabled);
+
+    Ok(enabled)
+}
+
+/// Stores a new, not yet active, secret for the user to add to their
+/// authenticator app. Returns `None` if two-factor authentication is
+/// already enabled: it has to be disabled before enrolling a new device.
+#[tracing::instrument(
+    name = "Start two-factor enrolment",
+    skip(pool)
+)]
+pub async fn start_two_factor_enrolment(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<Secret<String>>, anyhow::Error> {
+    let secret = totp::generate_secret();
+    let updated = sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_secret = $2, totp_last_used_step = NULL
+        WHERE user_id = $1 AND NOT totp_enabled
+        "#,
+        user_id,
+        secret.expose_secret(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the two-factor secret")?
+    .rows_affected();
+
+    Ok((updated > 0).then_some(secret))
+}
+
+/// Enables two-factor authentication once the user proved their app
+/// produces the right codes, returning freshly generated recovery codes.
+/// Returns `None` if the code was wrong or no enrolment was started.
+#[tracing::instrument(
+    name = "Confirm two-factor enrolment",
+    skip(code, pool)
+)]
+pub async fn confirm_two_factor_enrolment(
+    user_id: Uuid,
+    code: &str,
+    pool: &PgPool,
+) -> Result<Option<Vec<Secret<String>>>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let pending = sqlx::query!(
+        r#"SELECT totp_enabled FROM users WHERE user_id = $1 FOR UPDATE"#,
+        user_id,
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to retrieve the two-factor state")?;
+    if !matches!(pending, Some(row) if !row.totp_enabled) {
+        return Ok(None);
+    }
+    if !consume_totp_code(user_id, code, &mut transaction).await? {
+        return Ok(None);
+    }
+
+    sqlx::query!(
+        r#"UPDATE users SET totp_enabled = true WHERE user_id = $1"#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to enable two-factor authentication")?;
+    let recovery_codes = replace_recovery_codes(user_id, &mut transaction).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to enable two-factor authentication")?;
+
+    Ok(Some(recovery_codes))
+}
+
+/// Checks a code from the authenticator app or an unused recovery code.
+/// Either can only be used once.
+#[tracing::instrument(
+    name = "Verify second factor",
+    skip(code, pool)
+)]
+pub async fn verify_second_factor(
+    user_id: Uuid,
+    code: &str,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let verified = if code.trim().chars().all(|c| c.is_ascii_digit()) {
+        consume_totp_code(user_id, code, &mut transaction).await?
+    } else {
+        consume_recovery_code(user_id, code, &mut transaction).await?
+    };
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to verify a second factor")?;
+
+    Ok(verified)
+}
+
+#[tracing::instrument(
+    name = "Disable two-factor authentication",
+    skip(pool)
+)]
+pub async fn disable_two_factor(user_id: Uuid, pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_enabled = false, totp_secret = NULL, totp_last_used_step = NULL
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to disable two-factor authentication")?;
+    sqlx::query!(
+        r#"DELETE FROM recovery_codes WHERE user_id = $1"#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the recovery codes")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to disable two-factor authentication")?;
+
+    Ok(())
+}
+
+/// Accepts a TOTP code at most once: its time step must be later than the
+/// last accepted one.
+async fn consume_totp_code(
+    user_id: Uuid,
+    code: &str,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<bool, anyhow::Error> {
+    let secret = sqlx::query!(
+        r#"SELECT totp_secret FROM users WHERE user_id = $1"#,
+        user_id,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to retrieve the two-factor secret")?
+    .and_then(|row| row.totp_secret)
+    .map(Secret::new);
+    let secret = match secret {
+        Some(secret) => secret,
+        None => return Ok(false),
+    };
+
+    let step = match totp::verify_at(&secret, code, Utc::now().timestamp() as u64) {
+        Some(step) => step,
+        None => return Ok(false),
+    };
+    let updated = sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_last_used_step = $2
+        WHERE user_id = $1 AND (totp_last_used_step IS NULL OR totp_last_used_step < $2)
+        "#,
+        user_id,
+        step as i64,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to record the used two-factor code")?
+    .rows_affected();
+
+    Ok(updated > 0)
+}
+
+async fn consume_recovery_code(
+    user_id: Uuid,
+    code: &str,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<bool, anyhow::Error> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE recovery_codes
+        SET used_at = now()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+        user_id,
+        hash_recovery_code(code),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to use a recovery code")?
+    .rows_affected();
+
+    Ok(updated > 0)
+}
+
+async fn replace_recovery_codes(
+    user_id: Uuid,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Vec<Secret<String>>, anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM recovery_codes WHERE user_id = $1"#,
+        user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the previous recovery codes")?;
+
+    let codes: Vec<Secret<String>> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+    for code in &codes {
+        sqlx::query!(
+            r#"INSERT INTO recovery_codes (id, user_id, code_hash) VALUES ($1, $2, $3)"#,
+            Uuid::new_v4(),
+            user_id,
+            hash_recovery_code(code.expose_secret()),
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to store a recovery code")?;
+    }
+
+    Ok(codes)
+}
+
+/// Ten random characters shown as `xxxxx-xxxxx`.
+fn generate_recovery_code() -> Secret<String> {
+    let mut rng = thread_rng();
+    let characters: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .take(10)
+        .collect();
+    Secret::new(format!("{}-{}", &characters[..5], &characters[5..]))
+}
+
+/// Recovery codes are random enough that a fast hash is sufficient; unlike
+/// passwords they cannot be guessed from a dictionary.
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_codes_are_matched_regardless_of_formatting() {
+        let code = generate_recovery_code();
+        let sloppy = format!(" {} ", code.expose_secret().replace('-', "").to_uppercase());
+
+        assert_eq!(hash_recovery_code(code.expose_secret()), hash_recovery_code(&sloppy));
+    }
+}
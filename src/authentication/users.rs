@@ -0,0 +1,106 @@
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{validate_new_password, PasswordHashing, Role};
+use crate::telemetry::spawn_blocking_with_tracing;
+
+
+#[derive(thiserror::Error, Debug)]
+pub enum CreateUserError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The username `{0}` is already taken.")]
+    UsernameTaken(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// Creates a user directly, for bootstrapping and operators; everybody else
+/// joins through an invitation.
+#[tracing::instrument(
+    name = "Create user",
+    skip(password, password_hashing, pool)
+)]
+pub async fn create_user(
+    username: &str,
+    password: Secret<String>,
+    role: Role,
+    password_hashing: &PasswordHashing,
+    pool: &PgPool,
+) -> Result<Uuid, CreateUserError> {
+    let password_hash = hash_new_password(username, password, password_hashing).await?;
+    let user_id = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, role)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (username) DO NOTHING
+        RETURNING user_id
+        "#,
+        Uuid::new_v4(),
+        username,
+        password_hash.expose_secret(),
+        role.as_str(),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to store the user")?
+    .ok_or_else(|| CreateUserError::UsernameTaken(username.into()))?
+    .user_id;
+
+    Ok(user_id)
+}
+
+/// Creates an admin if, and only if, there are no users yet, so that it can
+/// run on every start of a deployment. Returns `None` if users already exist.
+#[tracing::instrument(
+    name = "Create initial admin",
+    skip(password, password_hashing, pool)
+)]
+pub async fn create_initial_admin(
+    username: &str,
+    password: Secret<String>,
+    password_hashing: &PasswordHashing,
+    pool: &PgPool,
+) -> Result<Option<Uuid>, CreateUserError> {
+    let password_hash = hash_new_password(username, password, password_hashing).await?;
+    // A single statement, so that instances starting at the same time cannot
+    // both see an empty table.
+    let user_id = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, role)
+        SELECT $1, $2, $3, 'admin'
+        WHERE NOT EXISTS (SELECT 1 FROM users)
+        ON CONFLICT (username) DO NOTHING
+        RETURNING user_id
+        "#,
+        Uuid::new_v4(),
+        username,
+        password_hash.expose_secret(),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to store the initial admin")?
+    .map(|row| row.user_id);
+
+    Ok(user_id)
+}
+
+async fn hash_new_password(
+    username: &str,
+    password: Secret<String>,
+    password_hashing: &PasswordHashing,
+) -> Result<Secret<String>, CreateUserError> {
+    if username.trim().is_empty() {
+        return Err(CreateUserError::ValidationError("The username must not be empty".into()));
+    }
+    validate_new_password(&password).map_err(CreateUserError::ValidationError)?;
+    let password_hashing = password_hashing.clone();
+    let password_hash = spawn_blocking_with_tracing(move || password_hashing.hash(password))
+        .await
+        .context("Failed to spawn blocking task.")?
+        .context("Failed to hash password")?;
+
+    Ok(password_hash)
+}
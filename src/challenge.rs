@@ -0,0 +1,123 @@
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+
+
+/// Checks the token a human-verification widget (captcha) added to a form.
+#[async_trait::async_trait]
+pub trait ChallengeVerifier: Send + Sync {
+    /// Returns `Ok(false)` when the provider rejected the token and an error
+    /// only when the provider could not be asked.
+    async fn verify(&self, response: &str, remote_ip: Option<&str>) -> Result<bool, anyhow::Error>;
+}
+
+/// Talks to any provider exposing the common `siteverify` protocol
+/// (reCAPTCHA, hCaptcha, Turnstile): a form-encoded `POST` of the secret and
+/// the token, answered with `{"success": bool}`.
+pub struct HttpChallengeVerifier {
+    http_client: Client,
+    verify_url: String,
+    secret_key: Secret<String>,
+}
+
+impl HttpChallengeVerifier {
+    pub fn new(
+        verify_url: String,
+        secret_key: Secret<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        let http_client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .unwrap();
+        Self { http_client, verify_url, secret_key }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+}
+
+#[async_trait::async_trait]
+impl ChallengeVerifier for HttpChallengeVerifier {
+    #[tracing::instrument(
+        name = "Verify challenge response",
+        skip(self, response)
+    )]
+    async fn verify(&self, response: &str, remote_ip: Option<&str>) -> Result<bool, anyhow::Error> {
+        let mut form = vec![
+            ("secret", self.secret_key.expose_secret().as_str()),
+            ("response", response),
+        ];
+        if let Some(remote_ip) = remote_ip {
+            form.push(("remoteip", remote_ip));
+        }
+
+        let outcome: SiteVerifyResponse = self.http_client
+            .post(&self.verify_url)
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(outcome.success)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::{assert_err, assert_ok_eq};
+    use wiremock::matchers::{body_string_contains, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn verifier(base_url: String) -> HttpChallengeVerifier {
+        HttpChallengeVerifier::new(
+            base_url,
+            Secret::new("challenge-secret".to_string()),
+            std::time::Duration::from_millis(200),
+        )
+    }
+
+    #[tokio::test]
+    async fn the_secret_and_response_are_sent_to_the_provider() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(body_string_contains("secret=challenge-secret"))
+            .and(body_string_contains("response=token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({"success": true})))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert_ok_eq!(verifier(mock_server.uri()).verify("token", None).await, true);
+    }
+
+    #[tokio::test]
+    async fn a_rejected_token_is_not_an_error() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({"success": false})))
+            .mount(&mock_server)
+            .await;
+
+        assert_ok_eq!(verifier(mock_server.uri()).verify("token", None).await, false);
+    }
+
+    #[tokio::test]
+    async fn an_unavailable_provider_is_an_error() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+
+        assert_err!(verifier(mock_server.uri()).verify("token", None).await);
+    }
+}
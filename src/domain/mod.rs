@@ -1,7 +1,9 @@
 mod new_subscriber;
 mod subscriber_name;
 mod subscriber_email;
+mod subscription_status;
 
-pub use new_subscriber::NewSubscriber;
-pub use subscriber_name::SubscriberName;
-pub use subscriber_email::SubscriberEmail;
\ No newline at end of file
+pub use new_subscriber::{NewSubscriber, NewSubscriberError};
+pub use subscriber_name::{SubscriberName, SubscriberNameError};
+pub use subscriber_email::{SubscriberEmail, SubscriberEmailError};
+pub use subscription_status::SubscriptionStatus;
@@ -1,8 +1,41 @@
-use crate::domain::subscriber_name::SubscriberName;
-use crate::domain::subscriber_email::SubscriberEmail;
+use crate::domain::subscriber_name::{SubscriberName, SubscriberNameError};
+use crate::domain::subscriber_email::{SubscriberEmail, SubscriberEmailError};
 
 #[derive(Debug)]
 pub struct NewSubscriber {
     pub name: SubscriberName,
     pub email: SubscriberEmail,
-}
\ No newline at end of file
+}
+
+/// Every invalid field of a new subscriber, so that they can all be reported
+/// at once.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum NewSubscriberError {
+    #[error(transparent)]
+    Name(SubscriberNameError),
+    #[error(transparent)]
+    Email(SubscriberEmailError),
+    #[error("{name}; {email}")]
+    Both {
+        name: SubscriberNameError,
+        email: SubscriberEmailError,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_invalid_field_is_described() {
+        let error = NewSubscriberError::Both {
+            name: SubscriberNameError::Empty,
+            email: SubscriberEmailError::InvalidSyntax("ursula".into()),
+        };
+
+        assert_eq!(
+            error.to_string(),
+            "The name is empty or whitespace; `ursula` is not a valid email address",
+        );
+    }
+}
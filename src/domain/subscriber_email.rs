@@ -5,14 +5,44 @@ use validator::validate_email;
 #[derive(Debug)]
 pub struct SubscriberEmail(String);
 
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum SubscriberEmailError {
+    #[error("The email address is empty")]
+    Empty,
+    #[error("`{0}` is not a valid email address")]
+    InvalidSyntax(String),
+    #[error("Addresses at `{0}` are not accepted")]
+    BlockedDomain(String),
+    #[error("`{0}` does not receive email")]
+    UndeliverableDomain(String),
+}
+
 impl SubscriberEmail {
-    pub fn parse(s: String) -> Result<Self, String> {
-        if validate_email(&s) {
-            Ok(Self(s))
+    /// Addresses are normalized: surrounding whitespace is dropped and the
+    /// domain is lowercased, internationalized domains in their punycode
+    /// form (`bücher.example` becomes `xn--bcher-kva.example`). The local
+    /// part is stored and mailed as entered, but addresses are compared
+    /// case-insensitively as a whole: the database keeps them unique on
+    /// `lower(email)` and looks them up the same way.
+    pub fn parse(s: String) -> Result<Self, SubscriberEmailError> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(SubscriberEmailError::Empty);
+        }
+        let invalid = || SubscriberEmailError::InvalidSyntax(s.to_string());
+        let (local_part, domain) = s.rsplit_once('@').ok_or_else(invalid)?;
+        let domain = idna::domain_to_ascii(domain).map_err(|_| invalid())?;
+        let email = format!("{}@{}", local_part, domain);
+        if validate_email(&email) {
+            Ok(Self(email))
         } else {
-            Err(format!("{} is not a valid email", s))
+            Err(invalid())
         }
     }
+
+    pub fn domain(&self) -> &str {
+        self.0.rsplit_once('@').map(|(_, domain)| domain).unwrap_or_default()
+    }
 }
 
 impl Display for SubscriberEmail {
@@ -31,7 +61,6 @@ impl AsRef<str> for SubscriberEmail {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use claim::assert_err;
     use fake::faker::internet::en::SafeEmail;
     use fake::Fake;
     use quickcheck::{Arbitrary, Gen};
@@ -49,19 +78,44 @@ mod tests {
     #[test]
     fn empty_string_is_rejected() {
         let email = "".to_string();
-        assert_err!(SubscriberEmail::parse(email));
+        assert_eq!(SubscriberEmail::parse(email).unwrap_err(), SubscriberEmailError::Empty);
     }
 
     #[test]
     fn email_missing_at_symbol_is_rejected() {
         let email = "ursuladomain.com".to_string();
-        assert_err!(SubscriberEmail::parse(email));
+        assert_eq!(
+            SubscriberEmail::parse(email).unwrap_err(),
+            SubscriberEmailError::InvalidSyntax("ursuladomain.com".into()),
+        );
     }
 
     #[test]
     fn email_missing_subject_is_rejected() {
         let email = "@domain.com".to_string();
-        assert_err!(SubscriberEmail::parse(email));
+        assert_eq!(
+            SubscriberEmail::parse(email).unwrap_err(),
+            SubscriberEmailError::InvalidSyntax("@domain.com".into()),
+        );
+    }
+
+    #[test]
+    fn emails_are_trimmed_and_their_domain_lowercased() {
+        let email = SubscriberEmail::parse("  Ursula@Example.COM \n".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "Ursula@example.com");
+        assert_eq!(email.domain(), "example.com");
+    }
+
+    #[test]
+    fn internationalized_domains_are_converted_to_punycode() {
+        let email = SubscriberEmail::parse("ursula@Bücher.example".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "ursula@xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn whitespace_only_is_rejected() {
+        let email = " \t".to_string();
+        assert_eq!(SubscriberEmail::parse(email).unwrap_err(), SubscriberEmailError::Empty);
     }
 
     #[quickcheck_macros::quickcheck]
@@ -1,19 +1,30 @@
 use unicode_segmentation::UnicodeSegmentation;
 
 
+const MAX_LENGTH: usize = 256;
+const FORBIDDEN_CHARACTERS: [char; 9] = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
+
 #[derive(Debug)]
 pub struct SubscriberName(String);
 
-impl SubscriberName {
-    pub fn parse(s: String) -> Result<Self, String> {
-        let forbidden_characters = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum SubscriberNameError {
+    #[error("The name is empty or whitespace")]
+    Empty,
+    #[error("The name is longer than {max} characters")]
+    TooLong { max: usize },
+    #[error("The name contains the forbidden character `{0}`")]
+    ForbiddenCharacter(char),
+}
 
+impl SubscriberName {
+    pub fn parse(s: String) -> Result<Self, SubscriberNameError> {
         if s.trim().is_empty() {
-            Err(format!("`{}` is empty or whitespace", s))
-        } else if s.graphemes(true).count() > 256 {
-            Err(format!("`{}` is longer than 256 characters", s))
-        } else if s.contains(&forbidden_characters) {
-            Err(format!("`{}` contains forbidden characters: {:?}", s, forbidden_characters))
+            Err(SubscriberNameError::Empty)
+        } else if s.graphemes(true).count() > MAX_LENGTH {
+            Err(SubscriberNameError::TooLong { max: MAX_LENGTH })
+        } else if let Some(c) = s.chars().find(|c| FORBIDDEN_CHARACTERS.contains(c)) {
+            Err(SubscriberNameError::ForbiddenCharacter(c))
         } else {
             Ok(Self(s))
         }
@@ -30,7 +41,7 @@ impl AsRef<str> for SubscriberName {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use claim::{assert_ok, assert_err};
+    use claim::assert_ok;
 
     #[test]
     fn a_256_grapheme_long_name_is_valid() {
@@ -41,26 +52,26 @@ mod tests {
     #[test]
     fn a_name_longer_than_256_graphemes_is_rejected() {
         let name = "a".repeat(257);
-        assert_err!(SubscriberName::parse(name));
+        assert_eq!(SubscriberName::parse(name).unwrap_err(), SubscriberNameError::TooLong { max: 256 });
     }
 
     #[test]
     fn whitespace_only_names_are_rejected() {
         let name = "\n \t\n".to_string();
-        assert_err!(SubscriberName::parse(name));
+        assert_eq!(SubscriberName::parse(name).unwrap_err(), SubscriberNameError::Empty);
     }
 
     #[test]
     fn empty_string_is_rejected() {
         let name = "".to_string();
-        assert_err!(SubscriberName::parse(name));
+        assert_eq!(SubscriberName::parse(name).unwrap_err(), SubscriberNameError::Empty);
     }
 
     #[test]
     fn names_containing_an_invalid_character_are_rejected() {
-        for name in &['/', '(', ')', '"', '<', '>', '\\', '{', '}'] {
-            let name = name.to_string();
-            assert_err!(SubscriberName::parse(name));
+        for c in FORBIDDEN_CHARACTERS {
+            let name = format!("Ursula {} Le Guin", c);
+            assert_eq!(SubscriberName::parse(name).unwrap_err(), SubscriberNameError::ForbiddenCharacter(c));
         }
     }
 
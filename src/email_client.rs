@@ -1,6 +1,7 @@
 use reqwest::Client;
 use secrecy::{ExposeSecret, Secret};
 use crate::domain::SubscriberEmail;
+use crate::telemetry::trace_context_headers;
 
 
 pub struct EmailClient {
@@ -43,6 +44,7 @@ impl EmailClient {
 
         let _builder = self.http_client
             .post(&url)
+            .headers(trace_context_headers())
             .header("X-Postmark-Server-Token", self.authorization_token.expose_secret())
             .json(&request_body)
             .send()
@@ -140,6 +142,38 @@ mod tests {
             .await;
     }
 
+    #[tokio::test]
+    async fn send_email_propagates_the_trace_context() {
+        use opentelemetry::trace::TracerProvider;
+        use tracing::Instrument;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        opentelemetry::global::set_text_map_propagator(
+            opentelemetry::sdk::propagation::TraceContextPropagator::new()
+        );
+        // The provider has to outlive the span, or the span gets no valid context.
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder().build();
+        let tracer = provider.tracer("test");
+        let subscriber = tracing_subscriber::Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer));
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("traceparent"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .instrument(tracing::info_span!("Send a test email"))
+            .await;
+
+        assert_ok!(outcome);
+    }
+
     #[tokio::test]
     async fn send_email_succeeds_if_the_server_returns_200() {
         let mock_server = MockServer::start().await;
@@ -0,0 +1,160 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use trust_dns_resolver::error::ResolveErrorKind;
+use trust_dns_resolver::TokioAsyncResolver;
+use crate::domain::{SubscriberEmail, SubscriberEmailError};
+
+
+/// Tells whether a domain can receive email.
+#[async_trait::async_trait]
+pub trait DomainResolver: Send + Sync {
+    /// Returns `Ok(false)` when the domain does not exist or has nowhere to
+    /// deliver mail to, and an error only when the lookup itself failed.
+    async fn accepts_mail(&self, domain: &str) -> Result<bool, anyhow::Error>;
+}
+
+/// Looks the domain up with the resolvers of the system: it must have an MX
+/// record or, failing that, an address mail can be delivered to directly
+/// (the implicit MX of RFC 5321).
+pub struct DnsDomainResolver {
+    resolver: TokioAsyncResolver,
+}
+
+impl DnsDomainResolver {
+    pub fn from_system_conf() -> Result<Self, anyhow::Error> {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf()?;
+        Ok(Self { resolver })
+    }
+}
+
+#[async_trait::async_trait]
+impl DomainResolver for DnsDomainResolver {
+    #[tracing::instrument(name = "Look up the mail exchangers of a domain", skip(self))]
+    async fn accepts_mail(&self, domain: &str) -> Result<bool, anyhow::Error> {
+        // A trailing dot keeps the search domains of the system out of it.
+        let fqdn = format!("{}.", domain);
+        match self.resolver.mx_lookup(fqdn.as_str()).await {
+            Ok(mx) if mx.iter().next().is_some() => return Ok(true),
+            Ok(_) => {},
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {},
+            Err(e) => return Err(e.into()),
+        }
+        match self.resolver.lookup_ip(fqdn.as_str()).await {
+            Ok(ips) => Ok(ips.iter().next().is_some()),
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// The domains subscribers may sign up with.
+pub struct EmailDomainPolicy {
+    blocked_domains: HashSet<String>,
+    resolver: Option<Arc<dyn DomainResolver>>,
+}
+
+impl EmailDomainPolicy {
+    /// Without a resolver, domains are not looked up.
+    pub fn new(blocked_domains: &[String], resolver: Option<Arc<dyn DomainResolver>>) -> Self {
+        let blocked_domains = blocked_domains
+            .iter()
+            .filter_map(|domain| idna::domain_to_ascii(domain.trim()).ok())
+            .filter(|domain| !domain.is_empty())
+            .collect();
+        Self { blocked_domains, resolver }
+    }
+
+    /// Blocked domains cover their subdomains too. DNS outages must not take
+    /// subscriptions down with them, so a failed lookup lets the address
+    /// through.
+    pub async fn check(&self, email: &SubscriberEmail) -> Result<(), SubscriberEmailError> {
+        let domain = email.domain();
+        if self.is_blocked(domain) {
+            return Err(SubscriberEmailError::BlockedDomain(domain.to_string()));
+        }
+        if let Some(resolver) = &self.resolver {
+            match resolver.accepts_mail(domain).await {
+                Ok(true) => {},
+                Ok(false) => return Err(SubscriberEmailError::UndeliverableDomain(domain.to_string())),
+                Err(e) => tracing::warn!(
+                    error.cause_chain = ?e,
+                    domain,
+                    "Failed to look up an email domain, accepting it",
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    fn is_blocked(&self, domain: &str) -> bool {
+        let mut candidate = domain;
+        loop {
+            if self.blocked_domains.contains(candidate) {
+                return true;
+            }
+            match candidate.split_once('.') {
+                Some((_, parent)) => candidate = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::{assert_err, assert_ok};
+
+    struct FakeResolver(Result<bool, &'static str>);
+
+    #[async_trait::async_trait]
+    impl DomainResolver for FakeResolver {
+        async fn accepts_mail(&self, _domain: &str) -> Result<bool, anyhow::Error> {
+            self.0.map_err(anyhow::Error::msg)
+        }
+    }
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.to_string()).unwrap()
+    }
+
+    fn policy(resolver: FakeResolver) -> EmailDomainPolicy {
+        EmailDomainPolicy::new(&["Mailinator.com".to_string()], Some(Arc::new(resolver)))
+    }
+
+    #[tokio::test]
+    async fn blocked_domains_and_their_subdomains_are_rejected() {
+        let policy = policy(FakeResolver(Ok(true)));
+
+        assert_eq!(
+            policy.check(&email("ursula@mailinator.com")).await,
+            Err(SubscriberEmailError::BlockedDomain("mailinator.com".into())),
+        );
+        assert_err!(policy.check(&email("ursula@eu.mailinator.com")).await);
+        assert_ok!(policy.check(&email("ursula@notmailinator.com")).await);
+    }
+
+    #[tokio::test]
+    async fn domains_that_do_not_accept_mail_are_rejected() {
+        let policy = policy(FakeResolver(Ok(false)));
+
+        assert_eq!(
+            policy.check(&email("ursula@example.invalid")).await,
+            Err(SubscriberEmailError::UndeliverableDomain("example.invalid".into())),
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_lookups_let_the_address_through() {
+        let policy = policy(FakeResolver(Err("SERVFAIL")));
+
+        assert_ok!(policy.check(&email("ursula@example.com")).await);
+    }
+
+    #[tokio::test]
+    async fn domains_are_not_looked_up_without_a_resolver() {
+        let policy = EmailDomainPolicy::new(&[], None);
+
+        assert_ok!(policy.check(&email("ursula@example.invalid")).await);
+    }
+}
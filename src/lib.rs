@@ -4,4 +4,13 @@ pub mod startup;
 pub mod telemetry;
 pub mod domain;
 pub mod email_client;
-pub mod authentication;
\ No newline at end of file
+pub mod email_validation;
+pub mod authentication;
+pub mod signed_link;
+pub mod rate_limit;
+pub mod challenge;
+pub mod session_state;
+pub mod worker;
+pub mod secrets;
+pub mod metrics;
+pub mod request_id;
@@ -1,22 +1,129 @@
-use zero2prod::configuration::get_configuration;
-use zero2prod::startup::run;
-use zero2prod::telemetry::{get_subscriber, init_subscriber};
-use sqlx::postgres::PgPoolOptions;
-use std::net::TcpListener;
-use secrecy::ExposeSecret;
-use zero2prod::email_client::EmailClient;
-use zero2prod::startup::Application;
+use std::io::BufRead;
+use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use secrecy::Secret;
+use zero2prod::authentication::{create_initial_admin, create_user, PasswordHashing, Role};
+use zero2prod::configuration::{get_configuration_for, Environment, Settings};
+use tracing_subscriber::layer::SubscriberExt;
+use zero2prod::telemetry::{get_otlp_layer, get_subscriber, init_subscriber, TracerProviderGuard};
+use zero2prod::startup::{get_connection_pool, migrate_database, Application};
+use zero2prod::worker::run_worker_until_stopped;
 
 
+/// One binary for every role of a deployment.
+#[derive(Parser)]
+#[command(name = "zero2prod")]
+struct Cli {
+	/// Serves the application when omitted.
+	#[command(subcommand)]
+	command: Option<Command>,
+	/// A file merged over the environment's settings; defaults to `APP_CONFIG_FILE`.
+	#[arg(long, global = true)]
+	config: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+	/// Serves the application over HTTP.
+	Serve,
+	/// Applies the migrations embedded in the binary and exits.
+	Migrate,
+	/// Loads and validates the configuration, reporting every problem, then exits.
+	CheckConfig {
+		/// Defaults to `APP_ENVIRONMENT`.
+		#[arg(long, value_parser = parse_environment)]
+		environment: Option<Environment>,
+	},
+	/// Runs the background jobs, without serving HTTP.
+	Worker,
+	/// Creates a user, reading the password from the first line of stdin.
+	CreateUser {
+		#[arg(long)]
+		username: String,
+		/// Required, so that passwords never end up in the shell history.
+		#[arg(long, required = true)]
+		password_stdin: bool,
+		#[arg(long, default_value = "admin", value_parser = parse_role)]
+		role: Role,
+	},
+}
+
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
-	let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
+async fn main() -> anyhow::Result<()> {
+	let cli = Cli::parse();
+
+	let command = cli.command.unwrap_or(Command::Serve);
+	let environment = match &command {
+		Command::CheckConfig { environment: Some(environment) } => environment.clone(),
+		_ => Environment::current().map_err(anyhow::Error::msg)?,
+	};
+	let file = cli.config.or_else(|| std::env::var_os("APP_CONFIG_FILE").map(PathBuf::from));
+	let configuration = get_configuration_for(environment, file.as_deref())?;
+
+	let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout)
+		.with(get_otlp_layer("zero2prod", &configuration.telemetry)?);
 	init_subscriber(subscriber);
+	let _tracer_provider = TracerProviderGuard;
 
-	let configuration = get_configuration().expect("fail to read configuration");
+	match command {
+		Command::Serve => {
+			// Building the application runs the migrations the bootstrap needs.
+			let application = Application::build(configuration.clone()).await?;
+			bootstrap_initial_admin(&configuration).await?;
+			application.run_until_stopped().await?;
+		},
+		Command::Migrate => {
+			let pool = get_connection_pool(&configuration.database);
+			migrate_database(&pool).await?;
+			tracing::info!("The database is migrated");
+		},
+		// Loading the configuration validated it.
+		Command::CheckConfig { .. } => println!("The configuration is valid"),
+		Command::Worker => run_worker_until_stopped(configuration).await?,
+		Command::CreateUser { username, role, .. } => {
+			let password = read_password_from_stdin()?;
+			let password_hashing = PasswordHashing::new(&configuration.password_hashing)?;
+			let pool = get_connection_pool(&configuration.database);
+			let user_id = create_user(&username, password, role, &password_hashing, &pool).await?;
+			println!("Created {} `{}` with id {}", role, username, user_id);
+		},
+	}
 
-	let application = Application::build(configuration)?;
-	application.run_until_stopped().await?;
+	Ok(())
+}
 
+/// First-run mode: with `APP_INITIAL_ADMIN_USERNAME` and
+/// `APP_INITIAL_ADMIN_PASSWORD` set, an admin is created as long as there
+/// are no users yet. Later starts leave the users table alone.
+async fn bootstrap_initial_admin(configuration: &Settings) -> anyhow::Result<()> {
+	let (username, password) = match (
+		std::env::var("APP_INITIAL_ADMIN_USERNAME"),
+		std::env::var("APP_INITIAL_ADMIN_PASSWORD"),
+	) {
+		(Ok(username), Ok(password)) => (username, Secret::new(password)),
+		_ => return Ok(()),
+	};
+
+	let password_hashing = PasswordHashing::new(&configuration.password_hashing)?;
+	let pool = get_connection_pool(&configuration.database);
+	match create_initial_admin(&username, password, &password_hashing, &pool).await? {
+		Some(user_id) => tracing::info!(%user_id, username = %username, "Created the initial admin"),
+		None => tracing::info!("Users already exist, the initial admin was not created"),
+	}
 	Ok(())
 }
+
+fn read_password_from_stdin() -> anyhow::Result<Secret<String>> {
+	let mut password = String::new();
+	std::io::stdin().lock().read_line(&mut password)?;
+	let password = password.trim_end_matches(['\r', '\n']).to_string();
+	Ok(Secret::new(password))
+}
+
+fn parse_role(s: &str) -> Result<Role, String> {
+	Role::try_from(s.to_string())
+}
+
+fn parse_environment(s: &str) -> Result<Environment, String> {
+	Environment::try_from(s.to_string())
+}
@@ -0,0 +1,136 @@
+use std::time::Instant;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::{web, HttpResponse};
+use actix_web_lab::middleware::Next;
+use once_cell::sync::Lazy;
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+use sqlx::PgPool;
+
+
+/// Metrics are process-wide, like the tracing subscriber, so that any code
+/// path can record them without threading them through.
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| register(IntCounterVec::new(
+	Opts::new("http_requests_total", "HTTP requests handled, by route and status"),
+	&["method", "route", "status"],
+)));
+
+static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| register(HistogramVec::new(
+	HistogramOpts::new("http_request_duration_seconds", "Time spent handling HTTP requests, by route and status"),
+	&["method", "route", "status"],
+)));
+
+static DB_POOL_CONNECTIONS: Lazy<IntGaugeVec> = Lazy::new(|| register(IntGaugeVec::new(
+	Opts::new("db_pool_connections", "Postgres connections in the pool, by state"),
+	&["state"],
+)));
+
+static EMAILS: Lazy<IntCounterVec> = Lazy::new(|| register(IntCounterVec::new(
+	Opts::new("emails_total", "Emails handed to the email provider, by kind and outcome"),
+	&["kind", "outcome"],
+)));
+
+static SUBSCRIPTION_EVENTS: Lazy<IntCounterVec> = Lazy::new(|| register(IntCounterVec::new(
+	Opts::new("subscription_events_total", "Steps of the subscription funnel"),
+	&["event"],
+)));
+
+fn register<M>(metric: Result<M, prometheus::Error>) -> M
+	where M: prometheus::core::Collector + Clone + 'static
+{
+	let metric = metric.expect("invalid metric definition");
+	REGISTRY.register(Box::new(metric.clone())).expect("metric registered twice");
+	metric
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum EmailKind {
+	Confirmation,
+	Newsletter,
+	Invitation,
+	PasswordReset,
+	DataExport,
+}
+
+impl EmailKind {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			EmailKind::Confirmation => "confirmation",
+			EmailKind::Newsletter => "newsletter",
+			EmailKind::Invitation => "invitation",
+			EmailKind::PasswordReset => "password_reset",
+			EmailKind::DataExport => "data_export",
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum SubscriptionEvent {
+	Subscribed,
+	Confirmed,
+	Unsubscribed,
+}
+
+impl SubscriptionEvent {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			SubscriptionEvent::Subscribed => "subscribed",
+			SubscriptionEvent::Confirmed => "confirmed",
+			SubscriptionEvent::Unsubscribed => "unsubscribed",
+		}
+	}
+}
+
+pub fn record_email<T, E>(kind: EmailKind, outcome: &Result<T, E>) {
+	let outcome = if outcome.is_ok() { "sent" } else { "failed" };
+	EMAILS.with_label_values(&[kind.as_str(), outcome]).inc();
+}
+
+pub fn record_subscription_event(event: SubscriptionEvent) {
+	SUBSCRIPTION_EVENTS.with_label_values(&[event.as_str()]).inc();
+}
+
+/// Counts and times every request. Routes are labelled with their pattern
+/// (`/admin/api/subscribers/{subscriber_id}`), not their path, to keep the
+/// number of series bounded.
+pub async fn track_requests(
+	req: ServiceRequest,
+	next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+	let started_at = Instant::now();
+	let method = req.method().to_string();
+	let response = next.call(req).await;
+
+	// The route is only known once the router has run. Errors of inner
+	// middleware have not been turned into responses yet and come without
+	// the request, so they count as unmatched.
+	let (route, status) = match &response {
+		Ok(response) => (response.request().match_pattern(), response.status()),
+		Err(e) => (None, e.as_response_error().status_code()),
+	};
+	let route = route.unwrap_or_else(|| "unmatched".into());
+	let status = status.as_u16().to_string();
+	let labels = [method.as_str(), route.as_str(), status.as_str()];
+	HTTP_REQUESTS.with_label_values(&labels).inc();
+	HTTP_REQUEST_DURATION.with_label_values(&labels).observe(started_at.elapsed().as_secs_f64());
+	response
+}
+
+/// Serves the metrics in the Prometheus text format.
+pub async fn metrics(pool: web::Data<PgPool>) -> HttpResponse {
+	let idle = pool.num_idle() as i64;
+	DB_POOL_CONNECTIONS.with_label_values(&["idle"]).set(idle);
+	DB_POOL_CONNECTIONS.with_label_values(&["in_use"]).set(pool.size() as i64 - idle);
+
+	let encoder = TextEncoder::new();
+	let mut buffer = Vec::new();
+	if let Err(e) = encoder.encode(&REGISTRY.gather(), &mut buffer) {
+		tracing::error!(error.cause_chain = ?e, "Failed to encode the metrics");
+		return HttpResponse::InternalServerError().finish();
+	}
+	HttpResponse::Ok()
+		.content_type(encoder.format_type())
+		.body(buffer)
+}
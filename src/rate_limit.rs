@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use actix_web::http::header::HeaderName;
+use actix_web::HttpRequest;
+use anyhow::Context;
+use redis::aio::ConnectionManager;
+use secrecy::{ExposeSecret, Secret};
+use tokio::sync::OnceCell;
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// Fixed-window counters keyed by arbitrary strings (e.g. `subscribe:ip:1.2.3.4`).
+///
+/// The in-memory variant is only correct for a single instance; deployments
+/// with several replicas must configure Redis so that they share counters.
+pub enum RateLimiter {
+    InMemory(InMemoryRateLimiter),
+    Redis(RedisRateLimiter),
+}
+
+impl RateLimiter {
+    pub fn in_memory() -> Self {
+        RateLimiter::InMemory(InMemoryRateLimiter::default())
+    }
+
+    pub fn redis(redis_uri: &Secret<String>) -> Result<Self, anyhow::Error> {
+        let client = redis::Client::open(redis_uri.expose_secret().as_str())
+            .context("Failed to parse the Redis URI")?;
+        Ok(RateLimiter::Redis(RedisRateLimiter { client, connection: OnceCell::new() }))
+    }
+
+    /// Counts a hit against `key` and returns whether it is within `limit`
+    /// hits for the current window.
+    pub async fn hit(&self, key: &str, limit: u32, window: Duration) -> Result<bool, anyhow::Error> {
+        Ok(self.increment(key, window).await? <= limit as u64)
+    }
+
+    /// Like `hit`, but a failing limiter lets the request through, so that
+    /// its outages do not take the endpoints it protects down with them.
+    pub async fn allows(&self, key: &str, limit: u32, window: Duration) -> bool {
+        match self.hit(key, limit, window).await {
+            Ok(true) => true,
+            Ok(false) => {
+                tracing::warn!(rate_limit_key = %key, "Rejected a request over the rate limit");
+                false
+            }
+            Err(e) => {
+                tracing::warn!(error.cause_chain = ?e, rate_limit_key = %key, "Failed to check a rate limit");
+                true
+            }
+        }
+    }
+
+    /// Counts a hit against `key` and returns the hits in the current window.
+    pub async fn increment(&self, key: &str, window: Duration) -> Result<u64, anyhow::Error> {
+        match self {
+            RateLimiter::InMemory(limiter) => Ok(limiter.hit(key, window)),
+            RateLimiter::Redis(limiter) => limiter.hit(key, window).await,
+        }
+    }
+
+    /// Whether the counters live in Redis rather than in this process.
+    pub fn is_shared(&self) -> bool {
+        matches!(self, RateLimiter::Redis(_))
+    }
+
+    /// Checks that Redis answers; always succeeds in memory.
+    pub async fn ping(&self) -> Result<(), anyhow::Error> {
+        match self {
+            RateLimiter::InMemory(_) => Ok(()),
+            RateLimiter::Redis(limiter) => limiter.ping().await,
+        }
+    }
+
+    /// The hits in the current window, without counting a new one.
+    pub async fn count(&self, key: &str) -> Result<u64, anyhow::Error> {
+        match self {
+            RateLimiter::InMemory(limiter) => Ok(limiter.count(key)),
+            RateLimiter::Redis(limiter) => limiter.count(key).await,
+        }
+    }
+}
+
+/// The address requests are attributed to. Each of the `trusted_proxy_hops`
+/// reverse proxies in front of the application appends the address it got
+/// the request from to `X-Forwarded-For`, so the client is that many entries
+/// from the right; entries further left are whatever the client sent. The
+/// peer address is used without trusted proxies, or when the header does
+/// not hold an address at that position.
+pub fn client_ip(request: &HttpRequest, trusted_proxy_hops: u32) -> Option<String> {
+    let peer_ip = || request.peer_addr().map(|addr| addr.ip().to_string());
+    if trusted_proxy_hops == 0 {
+        return peer_ip();
+    }
+
+    let forwarded: Vec<&str> = request.headers()
+        .get_all(X_FORWARDED_FOR)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    forwarded
+        .len()
+        .checked_sub(trusted_proxy_hops as usize)
+        .and_then(|index| forwarded[index].parse::<IpAddr>().ok())
+        .map(|ip| ip.to_string())
+        .or_else(peer_ip)
+}
+
+#[derive(Default)]
+pub struct InMemoryRateLimiter {
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+struct Window {
+    started_at: Instant,
+    expires_after: Duration,
+    count: u64,
+}
+
+/// Above this many tracked keys, expired windows are dropped on the next hit
+/// so that a flood of distinct keys cannot grow the map forever.
+const IN_MEMORY_PRUNE_THRESHOLD: usize = 10_000;
+
+impl InMemoryRateLimiter {
+    fn hit(&self, key: &str, window: Duration) -> u64 {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+        if windows.len() > IN_MEMORY_PRUNE_THRESHOLD {
+            windows.retain(|_, w| now.duration_since(w.started_at) < w.expires_after);
+        }
+
+        let entry = windows.entry(key.to_string()).or_insert(Window {
+            started_at: now,
+            expires_after: window,
+            count: 0,
+        });
+        if now.duration_since(entry.started_at) >= entry.expires_after {
+            *entry = Window { started_at: now, expires_after: window, count: 0 };
+        }
+        entry.count += 1;
+        entry.count
+    }
+
+    fn count(&self, key: &str) -> u64 {
+        let windows = self.windows.lock().unwrap();
+        windows
+            .get(key)
+            .filter(|w| w.started_at.elapsed() < w.expires_after)
+            .map_or(0, |w| w.count)
+    }
+}
+
+pub struct RedisRateLimiter {
+    client: redis::Client,
+    // Established on first use, so that the application starts while Redis is down.
+    connection: OnceCell<ConnectionManager>,
+}
+
+impl RedisRateLimiter {
+    async fn connection(&self) -> Result<ConnectionManager, anyhow::Error> {
+        let connection = self.connection
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await
+            .context("Failed to connect to Redis")?;
+        Ok(connection.clone())
+    }
+
+    async fn hit(&self, key: &str, window: Duration) -> Result<u64, anyhow::Error> {
+        let key = format!("rate_limit:{}", key);
+        let mut connection = self.connection().await?;
+        // `SET NX` starts the window (and its expiry) only for the first hit.
+        let (count,): (u64,) = redis::pipe()
+            .atomic()
+            .cmd("SET").arg(&key).arg(0).arg("EX").arg(window.as_secs().max(1)).arg("NX").ignore()
+            .cmd("INCR").arg(&key)
+            .query_async(&mut connection)
+            .await
+            .context("Failed to count a hit in Redis")?;
+        Ok(count)
+    }
+
+    async fn ping(&self) -> Result<(), anyhow::Error> {
+        let mut connection = self.connection().await?;
+        redis::cmd("PING")
+            .query_async::<_, String>(&mut connection)
+            .await
+            .context("Failed to ping Redis")?;
+        Ok(())
+    }
+
+    async fn count(&self, key: &str) -> Result<u64, anyhow::Error> {
+        let key = format!("rate_limit:{}", key);
+        let mut connection = self.connection().await?;
+        let count: Option<u64> = redis::cmd("GET")
+            .arg(&key)
+            .query_async(&mut connection)
+            .await
+            .context("Failed to read a hit count from Redis")?;
+        Ok(count.unwrap_or(0))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[tokio::test]
+    async fn hits_above_the_limit_are_rejected() {
+        let limiter = RateLimiter::in_memory();
+        let window = Duration::from_secs(60);
+
+        for _ in 0..3 {
+            assert!(limiter.hit("key", 3, window).await.unwrap());
+        }
+        assert!(!limiter.hit("key", 3, window).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn keys_are_counted_independently() {
+        let limiter = RateLimiter::in_memory();
+        let window = Duration::from_secs(60);
+
+        assert!(limiter.hit("a", 1, window).await.unwrap());
+        assert!(limiter.hit("b", 1, window).await.unwrap());
+        assert!(!limiter.hit("a", 1, window).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn counting_does_not_add_a_hit() {
+        let limiter = RateLimiter::in_memory();
+        let window = Duration::from_secs(60);
+
+        assert_eq!(limiter.count("key").await.unwrap(), 0);
+        limiter.increment("key", window).await.unwrap();
+        assert_eq!(limiter.count("key").await.unwrap(), 1);
+        assert_eq!(limiter.count("key").await.unwrap(), 1);
+    }
+
+    fn request(forwarded_for: Option<&str>) -> HttpRequest {
+        let mut request = TestRequest::default().peer_addr("10.0.0.1:4321".parse().unwrap());
+        if let Some(forwarded_for) = forwarded_for {
+            request = request.insert_header((X_FORWARDED_FOR, forwarded_for));
+        }
+        request.to_http_request()
+    }
+
+    #[test]
+    fn without_trusted_proxies_the_peer_is_the_client() {
+        assert_eq!(client_ip(&request(Some("203.0.113.7")), 0).as_deref(), Some("10.0.0.1"));
+    }
+
+    #[test]
+    fn the_client_is_found_counting_trusted_proxies_from_the_right() {
+        // The left-most entry was made up by the client.
+        let forwarded_for = "192.0.2.66, 203.0.113.7, 198.51.100.2";
+
+        assert_eq!(client_ip(&request(Some(forwarded_for)), 1).as_deref(), Some("198.51.100.2"));
+        assert_eq!(client_ip(&request(Some(forwarded_for)), 2).as_deref(), Some("203.0.113.7"));
+    }
+
+    #[test]
+    fn the_peer_is_the_client_when_the_header_is_missing_or_short() {
+        assert_eq!(client_ip(&request(None), 1).as_deref(), Some("10.0.0.1"));
+        assert_eq!(client_ip(&request(Some("203.0.113.7")), 2).as_deref(), Some("10.0.0.1"));
+        assert_eq!(client_ip(&request(Some("not-an-ip")), 1).as_deref(), Some("10.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn the_count_starts_over_once_the_window_has_elapsed() {
+        let limiter = RateLimiter::in_memory();
+        let window = Duration::from_millis(10);
+
+        assert!(limiter.hit("key", 1, window).await.unwrap());
+        assert!(!limiter.hit("key", 1, window).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(limiter.hit("key", 1, window).await.unwrap());
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt::Display;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::HttpMessage;
+use actix_web_lab::middleware::Next;
+
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+tokio::task_local! {
+	static REQUEST_ID: RequestId;
+}
+
+/// Identifies a request in the logs. Also available to handlers through
+/// `web::ReqData<RequestId>`.
+#[derive(Clone, Debug)]
+pub struct RequestId(String);
+
+impl Display for RequestId {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		self.0.fmt(f)
+	}
+}
+
+impl AsRef<str> for RequestId {
+	fn as_ref(&self) -> &str {
+		&self.0
+	}
+}
+
+/// The id of the request being handled, for error responses, which have no
+/// access to the request.
+pub fn current_request_id() -> Option<RequestId> {
+	REQUEST_ID.try_with(|request_id| request_id.clone()).ok()
+}
+
+/// Adopts the `X-Request-Id` of the caller (a proxy, another service), when
+/// it is well-formed, or the id `TracingLogger` generated, and returns it in
+/// the `X-Request-Id` response header. Must run inside `TracingLogger`, so
+/// that the root span is the current one.
+pub async fn propagate_request_id(
+	req: ServiceRequest,
+	next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+	let request_id = req.headers()
+		.get(&X_REQUEST_ID)
+		.and_then(|value| value.to_str().ok())
+		.filter(|value| is_valid_request_id(value))
+		.map(|value| value.to_string())
+		.or_else(|| req.extensions().get::<tracing_actix_web::RequestId>().map(|id| id.to_string()))
+		.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+	let request_id = RequestId(request_id);
+	tracing::Span::current().record("request_id", tracing::field::display(&request_id));
+	req.extensions_mut().insert(request_id.clone());
+
+	let header_value = HeaderValue::from_str(request_id.as_ref()).ok();
+	match REQUEST_ID.scope(request_id.clone(), next.call(req)).await {
+		Ok(response) => {
+			let mut response = response.map_into_boxed_body();
+			if let Some(value) = header_value {
+				response.headers_mut().insert(X_REQUEST_ID, value);
+			}
+			Ok(response)
+		}
+		// Errors of inner middleware are rendered here, so that they carry
+		// the request id as well. They stay errors: there is no request to
+		// build a response with, since holding on to it would keep the router
+		// from recording the matched route.
+		Err(e) => {
+			let mut response = REQUEST_ID.sync_scope(request_id, || e.error_response());
+			if let Some(value) = header_value {
+				response.headers_mut().insert(X_REQUEST_ID, value);
+			}
+			Err(InternalError::from_response(e, response).into())
+		}
+	}
+}
+
+fn is_valid_request_id(value: &str) -> bool {
+	!value.is_empty()
+		&& value.len() <= MAX_REQUEST_ID_LENGTH
+		&& value.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn only_short_plain_request_ids_are_adopted() {
+		assert!(is_valid_request_id("4bf92f35-77b3-4da6-a3ce-929d0e0e4736"));
+		assert!(is_valid_request_id("req_01.A"));
+		assert!(!is_valid_request_id(""));
+		assert!(!is_valid_request_id("id with spaces"));
+		assert!(!is_valid_request_id("<script>"));
+		assert!(!is_valid_request_id(&"a".repeat(MAX_REQUEST_ID_LENGTH + 1)));
+	}
+}
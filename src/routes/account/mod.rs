@@ -0,0 +1,3 @@
+mod two_factor;
+
+pub use two_factor::*;
@@ -0,0 +1,225 @@
+use std::fmt::Debug;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse, ResponseError, http::StatusCode};
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{
+    confirm_two_factor_enrolment, disable_two_factor, is_two_factor_enabled,
+    start_two_factor_enrolment, totp, verify_second_factor, UserId,
+};
+use crate::routes::{api_error_response, error_chain_fmt, ApiError};
+
+
+/// Shown by authenticator apps next to the account name.
+const TOTP_ISSUER: &str = "zero2prod";
+
+#[derive(serde::Deserialize)]
+pub struct CodeFormData {
+    code: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum TwoFactorSettingsError {
+    #[error("The code is invalid")]
+    InvalidCode,
+    #[error("Two-factor authentication is already enabled")]
+    AlreadyEnabled,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for TwoFactorSettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(f, self)
+    }
+}
+
+impl ResponseError for TwoFactorSettingsError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            TwoFactorSettingsError::InvalidCode => StatusCode::BAD_REQUEST,
+            TwoFactorSettingsError::AlreadyEnabled => StatusCode::CONFLICT,
+            TwoFactorSettingsError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        api_error_response(self)
+    }
+}
+
+impl ApiError for TwoFactorSettingsError {
+    fn error_code(&self) -> &'static str {
+        match self {
+            TwoFactorSettingsError::InvalidCode => "invalid_code",
+            TwoFactorSettingsError::AlreadyEnabled => "two_factor_already_enabled",
+            TwoFactorSettingsError::UnexpectedError(_) => "internal_error",
+        }
+    }
+}
+
+
+#[tracing::instrument(
+    name = "Show two-factor settings",
+    skip(pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn two_factor_settings(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, TwoFactorSettingsError> {
+    let body = if is_two_factor_enabled(**user_id, &pool).await? {
+        r#"
+        <p>Two-factor authentication is enabled.</p>
+        <form action="/account/two_factor/disable" method="post">
+            <label>Current code or a recovery code
+                <input type="text" autocomplete="one-time-code" name="code">
+            </label>
+            <button type="submit">Disable two-factor authentication</button>
+        </form>
+        "#
+    } else {
+        r#"
+        <p>Two-factor authentication is disabled.</p>
+        <form action="/account/two_factor/enrol" method="post">
+            <button type="submit">Set up an authenticator app</button>
+        </form>
+        "#
+    };
+
+    Ok(html_page("Two-factor authentication", body))
+}
+
+#[tracing::instrument(
+    name = "Enrol in two-factor authentication",
+    skip(pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn enrol_two_factor(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, TwoFactorSettingsError> {
+    let secret = start_two_factor_enrolment(**user_id, &pool)
+        .await?
+        .ok_or(TwoFactorSettingsError::AlreadyEnabled)?;
+    let username = get_username(**user_id, &pool).await?;
+    let uri = totp::otpauth_uri(TOTP_ISSUER, &username, &secret);
+
+    Ok(html_page(
+        "Set up two-factor authentication",
+        &format!(
+            r#"
+            <p>Scan this link as a QR code, or open it, with your authenticator app:</p>
+            <p><a href="{uri}"><code>{uri}</code></a></p>
+            <p>If your app asks for a key instead, enter <code>{secret}</code>.</p>
+            <form action="/account/two_factor/confirm" method="post">
+                <label>Code shown by the app
+                    <input type="text" autocomplete="one-time-code" name="code">
+                </label>
+                <button type="submit">Enable two-factor authentication</button>
+            </form>
+            "#,
+            uri = htmlescape::encode_minimal(&uri),
+            secret = secret.expose_secret(),
+        ),
+    ))
+}
+
+#[tracing::instrument(
+    name = "Confirm two-factor enrolment",
+    skip(form, pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn confirm_two_factor(
+    form: web::Form<CodeFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, TwoFactorSettingsError> {
+    let recovery_codes = confirm_two_factor_enrolment(**user_id, form.0.code.expose_secret(), &pool)
+        .await?
+        .ok_or(TwoFactorSettingsError::InvalidCode)?;
+    tracing::info!(
+        target: "security",
+        security_event = "two_factor_enabled",
+        user_id = %**user_id,
+        "Enabled two-factor authentication"
+    );
+
+    let recovery_codes: String = recovery_codes
+        .iter()
+        .map(|code| format!("<li><code>{}</code></li>", code.expose_secret()))
+        .collect();
+    Ok(html_page(
+        "Two-factor authentication enabled",
+        &format!(
+            r#"
+            <p>Two-factor authentication is enabled.</p>
+            <p>Store these recovery codes somewhere safe. Each of them lets you log in once without your
+            authenticator app, and they will not be shown again:</p>
+            <ul>{recovery_codes}</ul>
+            "#,
+        ),
+    ))
+}
+
+#[tracing::instrument(
+    name = "Disable two-factor authentication",
+    skip(form, pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn disable_two_factor_authentication(
+    form: web::Form<CodeFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, TwoFactorSettingsError> {
+    if !verify_second_factor(**user_id, form.0.code.expose_secret(), &pool).await? {
+        return Err(TwoFactorSettingsError::InvalidCode);
+    }
+    disable_two_factor(**user_id, &pool).await?;
+    tracing::info!(
+        target: "security",
+        security_event = "two_factor_disabled",
+        user_id = %**user_id,
+        "Disabled two-factor authentication"
+    );
+
+    Ok(html_page(
+        "Two-factor authentication disabled",
+        "<p>Two-factor authentication is disabled.</p>",
+    ))
+}
+
+async fn get_username(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
+    let username = sqlx::query!(
+        r#"SELECT username FROM users WHERE user_id = $1"#,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the username")?
+    .username;
+
+    Ok(username)
+}
+
+fn html_page(title: &str, body: &str) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>{title}</title>
+            </head>
+            <body>
+                {body}
+            </body>
+            </html>
+            "#,
+        ))
+}
@@ -0,0 +1,207 @@
+use std::fmt::Debug;
+use actix_web::{web, HttpResponse, ResponseError, http::StatusCode};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{generate_api_token, hash_api_token, ApiScope, Principal, UserId};
+use crate::routes::{api_error_response, error_chain_fmt, ApiError};
+
+
+const MAX_TOKEN_NAME_LENGTH: usize = 100;
+
+#[derive(serde::Deserialize)]
+pub struct CreateApiTokenBody {
+    name: String,
+    scopes: Vec<String>,
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ApiToken {
+    pub id: Uuid,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// The only time the token itself is shown; afterwards just its hash is stored.
+#[derive(serde::Serialize)]
+pub struct CreatedApiToken {
+    #[serde(flatten)]
+    pub api_token: ApiToken,
+    pub token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum ApiTokenError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("There is no API token with the provided id")]
+    NotFound,
+    #[error("{0}")]
+    Forbidden(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for ApiTokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(f, self)
+    }
+}
+
+impl ResponseError for ApiTokenError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiTokenError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ApiTokenError::NotFound => StatusCode::NOT_FOUND,
+            ApiTokenError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiTokenError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        api_error_response(self)
+    }
+}
+
+impl ApiError for ApiTokenError {
+    fn error_code(&self) -> &'static str {
+        match self {
+            ApiTokenError::ValidationError(_) => "validation_error",
+            ApiTokenError::NotFound => "api_token_not_found",
+            ApiTokenError::Forbidden(_) => "forbidden",
+            ApiTokenError::UnexpectedError(_) => "internal_error",
+        }
+    }
+}
+
+
+#[tracing::instrument(
+    name = "Create an API token",
+    skip(body, pool, principal),
+    fields(user_id = %*user_id)
+)]
+pub async fn create_api_token(
+    body: web::Json<CreateApiTokenBody>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    principal: web::ReqData<Principal>,
+) -> Result<HttpResponse, ApiTokenError> {
+    let body = body.into_inner();
+    let name = body.name.trim().to_string();
+    if name.is_empty() || name.len() > MAX_TOKEN_NAME_LENGTH {
+        return Err(ApiTokenError::ValidationError(
+            format!("`name` must be between 1 and {} characters", MAX_TOKEN_NAME_LENGTH)
+        ));
+    }
+    if body.scopes.is_empty() {
+        return Err(ApiTokenError::ValidationError("`scopes` must not be empty".into()));
+    }
+    let scopes = body.scopes
+        .into_iter()
+        .map(ApiScope::try_from)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(ApiTokenError::ValidationError)?;
+    // Tokens act on behalf of their owner and cannot do more than them.
+    if let Some(scope) = scopes.iter().find(|scope| !principal.role.grants(scope.permission())) {
+        return Err(ApiTokenError::Forbidden(
+            format!("The `{}` role cannot create tokens with the `{}` scope", principal.role, scope)
+        ));
+    }
+    let mut scopes: Vec<String> = scopes.iter().map(|scope| scope.as_str().to_string()).collect();
+    scopes.sort();
+    scopes.dedup();
+    let created_at = Utc::now();
+    if body.expires_at.is_some_and(|expires_at| expires_at <= created_at) {
+        return Err(ApiTokenError::ValidationError("`expires_at` must be in the future".into()));
+    }
+
+    let token = generate_api_token();
+    let api_token = sqlx::query_as!(
+        ApiToken,
+        r#"
+        INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, name, scopes, created_at, expires_at, last_used_at
+        "#,
+        Uuid::new_v4(),
+        **user_id,
+        name,
+        hash_api_token(&token),
+        &scopes,
+        created_at,
+        body.expires_at,
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to store the API token")?;
+
+    Ok(HttpResponse::Created().json(CreatedApiToken {
+        api_token,
+        token: token.expose_secret().clone(),
+    }))
+}
+
+/// Lists the tokens of the authenticated user that have not been revoked.
+#[tracing::instrument(
+    name = "List API tokens",
+    skip(pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn list_api_tokens(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, ApiTokenError> {
+    let api_tokens = sqlx::query_as!(
+        ApiToken,
+        r#"
+        SELECT id, name, scopes, created_at, expires_at, last_used_at
+        FROM api_tokens
+        WHERE user_id = $1 AND revoked_at IS NULL
+        ORDER BY created_at DESC
+        "#,
+        **user_id,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the API tokens")?;
+
+    Ok(HttpResponse::Ok().json(api_tokens))
+}
+
+#[tracing::instrument(
+    name = "Revoke an API token",
+    skip(pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn revoke_api_token(
+    token_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, ApiTokenError> {
+    let revoked = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET revoked_at = now()
+        WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        *token_id,
+        **user_id,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to revoke the API token")?
+    .rows_affected();
+    if revoked == 0 {
+        return Err(ApiTokenError::NotFound);
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,9 @@
+mod api_tokens;
+mod subscribers;
+mod subscribers_csv;
+mod users;
+
+pub use api_tokens::*;
+pub use subscribers::*;
+pub use subscribers_csv::*;
+pub use users::*;
@@ -0,0 +1,503 @@
+use std::fmt::Debug;
+use actix_web::{web, HttpResponse, ResponseError, http::StatusCode};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName, SubscriptionStatus};
+use crate::email_client::EmailClient;
+use crate::metrics::{record_subscription_event, SubscriptionEvent};
+use crate::routes::{
+    api_error_response, confirm_subscriber, enqueue_confirmation_email, error_chain_fmt, erase_subscriber,
+    generate_subscriptions_token, get_consent_events, record_confirmed_event, send_confirmation_email,
+    store_token, ApiError, ConsentContext,
+};
+use crate::startup::{ApplicationBaseUrl, ConsentTextVersion, HmacSecret};
+
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(serde::Serialize)]
+pub struct Subscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub tags: Vec<String>,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberPage {
+    pub subscribers: Vec<Subscriber>,
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListQuery {
+    status: Option<String>,
+    search: Option<String>,
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct UpdateBody {
+    email: Option<String>,
+    name: Option<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum AdminApiError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("There is no subscriber with the provided id")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for AdminApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(f, self)
+    }
+}
+
+impl ResponseError for AdminApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AdminApiError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            AdminApiError::NotFound => StatusCode::NOT_FOUND,
+            AdminApiError::Conflict(_) => StatusCode::CONFLICT,
+            AdminApiError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        api_error_response(self)
+    }
+}
+
+impl ApiError for AdminApiError {
+    fn error_code(&self) -> &'static str {
+        match self {
+            AdminApiError::ValidationError(_) => "validation_error",
+            AdminApiError::NotFound => "subscriber_not_found",
+            AdminApiError::Conflict(_) => "conflict",
+            AdminApiError::UnexpectedError(_) => "internal_error",
+        }
+    }
+}
+
+
+#[tracing::instrument(
+    name = "List subscribers",
+    skip(query, pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn list_subscribers(
+    query: web::Query<ListQuery>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, AdminApiError> {
+    let query = query.into_inner();
+    let status = query.status
+        .map(SubscriptionStatus::try_from)
+        .transpose()
+        .map_err(AdminApiError::ValidationError)?;
+    let page = query.page.unwrap_or(1);
+    if page < 1 {
+        return Err(AdminApiError::ValidationError("`page` must be at least 1".into()));
+    }
+    let per_page = query.per_page.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&per_page) {
+        return Err(AdminApiError::ValidationError(
+            format!("`per_page` must be between 1 and {}", MAX_PAGE_SIZE)
+        ));
+    }
+    let pattern = query.search
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| format!("%{}%", escape_like_pattern(s.trim())));
+    let status = status.map(|s| s.as_str());
+
+    let subscribers = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, tags, subscribed_at
+        FROM subscriptions
+        WHERE ($1::TEXT IS NULL OR status = $1)
+            AND ($2::TEXT IS NULL OR email ILIKE $2 OR name ILIKE $2)
+        ORDER BY subscribed_at DESC, id
+        LIMIT $3 OFFSET $4
+        "#,
+        status,
+        pattern,
+        per_page,
+        (page - 1) * per_page,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch a page of subscribers")?;
+
+    let total = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "total!"
+        FROM subscriptions
+        WHERE ($1::TEXT IS NULL OR status = $1)
+            AND ($2::TEXT IS NULL OR email ILIKE $2 OR name ILIKE $2)
+        "#,
+        status,
+        pattern,
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to count subscribers")?
+    .total;
+
+    Ok(HttpResponse::Ok().json(SubscriberPage { subscribers, page, per_page, total }))
+}
+
+#[tracing::instrument(
+    name = "Get a subscriber",
+    skip(pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn get_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, AdminApiError> {
+    let subscriber = get_subscriber_by_id(*subscriber_id, pool.get_ref())
+        .await
+        .context("Failed to fetch the subscriber")?
+        .ok_or(AdminApiError::NotFound)?;
+
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+#[tracing::instrument(
+    name = "List consent events of a subscriber",
+    skip(pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn list_consent_events(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, AdminApiError> {
+    get_subscriber_by_id(*subscriber_id, pool.get_ref())
+        .await
+        .context("Failed to fetch the subscriber")?
+        .ok_or(AdminApiError::NotFound)?;
+    let consent_events = get_consent_events(*subscriber_id, &pool)
+        .await
+        .context("Failed to fetch the consent events of the subscriber")?;
+
+    Ok(HttpResponse::Ok().json(consent_events))
+}
+
+/// A new email has not been confirmed by anyone: the subscriber goes back to
+/// `pending_confirmation`, their old confirmation links stop working and a
+/// confirmation email to the new address is queued. Unsubscribed subscribers
+/// stay unsubscribed and get no email.
+#[tracing::instrument(
+    name = "Update a subscriber",
+    skip(body, pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn update_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    body: web::Json<UpdateBody>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, AdminApiError> {
+    let body = body.into_inner();
+    let email = body.email
+        .map(SubscriberEmail::parse)
+        .transpose()
+        .map_err(|e| AdminApiError::ValidationError(e.to_string()))?;
+    let name = body.name
+        .map(SubscriberName::parse)
+        .transpose()
+        .map_err(|e| AdminApiError::ValidationError(e.to_string()))?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let current = sqlx::query!(
+        r#"SELECT email, status FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        *subscriber_id,
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to fetch the subscriber")?
+    .ok_or(AdminApiError::NotFound)?;
+    let email_changed = email
+        .as_ref()
+        .is_some_and(|email| !email.as_ref().eq_ignore_ascii_case(&current.email));
+    let reconfirm = email_changed && current.status != SubscriptionStatus::Unsubscribed.as_str();
+
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        UPDATE subscriptions
+        SET
+            email = COALESCE($2, email),
+            name = COALESCE($3, name),
+            status = CASE WHEN $4 THEN 'pending_confirmation' ELSE status END
+        WHERE id = $1
+        RETURNING id, email, name, status, tags, subscribed_at
+        "#,
+        *subscriber_id,
+        email.as_ref().map(|e| e.as_ref()),
+        name.as_ref().map(|n| n.as_ref()),
+        reconfirm,
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .map_err(|e| match &e {
+        sqlx::Error::Database(db_error) if db_error.code().as_deref() == Some("23505") => {
+            AdminApiError::Conflict("Another subscriber already uses this email".into())
+        },
+        _ => AdminApiError::UnexpectedError(
+            anyhow::Error::new(e).context("Failed to update the subscriber")
+        ),
+    })?;
+
+    if email_changed {
+        // The links sent to the old address must not confirm the new one.
+        sqlx::query!(
+            r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+            *subscriber_id,
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete the confirmation tokens of the old email")?;
+    }
+    if reconfirm {
+        let subscription_token = generate_subscriptions_token();
+        store_token(subscriber.id, &subscription_token, &mut transaction)
+            .await
+            .context("Failed to store a confirmation token for the new email")?;
+        enqueue_confirmation_email(subscriber.id, &subscription_token, &mut transaction)
+            .await
+            .context("Failed to queue a confirmation email for the new email")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update the subscriber")?;
+
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+/// Only pending subscribers can be confirmed, so that an admin cannot sign
+/// someone back up who has unsubscribed.
+#[tracing::instrument(
+    name = "Manually confirm a subscriber",
+    skip(pool, consent_text_version),
+    fields(user_id = %*user_id)
+)]
+pub async fn mark_subscriber_confirmed(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    consent_text_version: web::Data<ConsentTextVersion>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, AdminApiError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let confirmed = confirm_subscriber(*subscriber_id, &mut transaction)
+        .await
+        .context("Failed to update the subscriber status to `confirmed`")?;
+    if !confirmed {
+        let subscriber = get_subscriber_by_id(*subscriber_id, &mut transaction)
+            .await
+            .context("Failed to fetch the subscriber")?
+            .ok_or(AdminApiError::NotFound)?;
+        return Err(AdminApiError::Conflict(
+            format!("The subscriber is `{}`, not `pending_confirmation`", subscriber.status)
+        ));
+    }
+    let context = ConsentContext::recorded_by(**user_id);
+    record_confirmed_event(*subscriber_id, &context, "admin", &consent_text_version.0, &mut transaction)
+        .await
+        .context("Failed to record the consent of a confirmed subscriber")?;
+    let subscriber = get_subscriber_by_id(*subscriber_id, &mut transaction)
+        .await
+        .context("Failed to fetch the subscriber")?
+        .ok_or(AdminApiError::NotFound)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber")?;
+    record_subscription_event(SubscriptionEvent::Confirmed);
+
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+#[tracing::instrument(
+    name = "Manually unsubscribe a subscriber",
+    skip(pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn mark_subscriber_unsubscribed(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, AdminApiError> {
+    let subscriber = set_subscriber_status(*subscriber_id, SubscriptionStatus::Unsubscribed, &pool)
+        .await
+        .context("Failed to update the subscriber status to `unsubscribed`")?
+        .ok_or(AdminApiError::NotFound)?;
+    record_subscription_event(SubscriptionEvent::Unsubscribed);
+
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+/// Erases the subscriber like a data erasure request would, consent events
+/// being pseudonymised rather than deleted.
+#[tracing::instrument(
+    name = "Delete a subscriber",
+    skip(pool, hmac_secret, consent_text_version),
+    fields(user_id = %*user_id)
+)]
+pub async fn delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    consent_text_version: web::Data<ConsentTextVersion>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, AdminApiError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let deleted = erase_subscriber(
+        *subscriber_id,
+        "admin",
+        Some(**user_id),
+        &consent_text_version.0,
+        &hmac_secret,
+        &mut transaction,
+    )
+        .await
+        .context("Failed to delete the subscriber")?;
+    if !deleted {
+        return Err(AdminApiError::NotFound);
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to delete a subscriber")?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(
+    name = "Resend a confirmation email",
+    skip(pool, email_client, base_url),
+    fields(user_id = %*user_id)
+)]
+pub async fn resend_confirmation_email(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, AdminApiError> {
+    let subscriber = get_subscriber_by_id(*subscriber_id, pool.get_ref())
+        .await
+        .context("Failed to fetch the subscriber")?
+        .ok_or(AdminApiError::NotFound)?;
+    if subscriber.status != SubscriptionStatus::PendingConfirmation.as_str() {
+        return Err(AdminApiError::Conflict(
+            format!("The subscriber is `{}`, not `pending_confirmation`", subscriber.status)
+        ));
+    }
+    let new_subscriber = NewSubscriber {
+        email: SubscriberEmail::parse(subscriber.email.clone()).map_err(|e| anyhow::anyhow!(e))?,
+        name: SubscriberName::parse(subscriber.name.clone()).map_err(|e| anyhow::anyhow!(e))?,
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let subscription_token = generate_subscriptions_token();
+    store_token(subscriber.id, &subscription_token, &mut transaction)
+        .await
+        .context("Failed to store a new confirmation token for the subscriber")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a confirmation token")?;
+
+    send_confirmation_email(&email_client, new_subscriber, &base_url.0, &subscription_token)
+        .await
+        .context("Failed to send a confirmation email")?;
+
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+/// Takes a pool or a transaction, to return what an update left behind.
+#[tracing::instrument(
+    name = "Get subscriber by id",
+    skip(executor)
+)]
+async fn get_subscriber_by_id(
+    subscriber_id: Uuid,
+    executor: impl PgExecutor<'_>,
+) -> Result<Option<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, tags, subscribed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+#[tracing::instrument(
+    name = "Set subscriber status",
+    skip(pool)
+)]
+async fn set_subscriber_status(
+    subscriber_id: Uuid,
+    status: SubscriptionStatus,
+    pool: &PgPool,
+) -> Result<Option<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        UPDATE subscriptions SET status = $2
+        WHERE id = $1
+        RETURNING id, email, name, status, tags, subscribed_at
+        "#,
+        subscriber_id,
+        status.as_str(),
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// `search` is matched as a substring, so `%`, `_` and the escape
+/// character itself must not be interpreted by `ILIKE`.
+fn escape_like_pattern(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
@@ -0,0 +1,665 @@
+use std::collections::HashSet;
+use std::convert::Infallible;
+use actix_web::{web, HttpResponse};
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use csv_core::ReadRecordResult;
+use futures_util::stream::{self, StreamExt};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::domain::{NewSubscriber, SubscriptionStatus};
+use crate::routes::{
+    enqueue_confirmation_email, generate_subscriptions_token, record_confirmed_event, record_subscribed_event,
+    store_token, AdminApiError, ConsentContext, FormData,
+};
+use crate::startup::ConsentTextVersion;
+
+
+const EXPORT_BATCH_SIZE: i64 = 500;
+const IMPORT_BATCH_SIZE: usize = 500;
+/// At most this many invalid rows are described, to keep the report on a
+/// badly broken file small.
+const MAX_REPORTED_ERRORS: usize = 100;
+const TAG_SEPARATOR: char = ';';
+/// Ends an export that failed after the response started.
+const EXPORT_FAILED_MARKER: &str = "# The export failed: the subscribers above are incomplete\n";
+const CONSENT_SOURCE: &str = "csv_import";
+
+#[derive(serde::Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default)]
+    send_confirmation: bool,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ExportQuery {
+    status: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub imported: u64,
+    pub confirmation_emails_queued: u64,
+    /// The first `MAX_REPORTED_ERRORS` invalid rows.
+    pub errors: Vec<RowError>,
+    /// How many invalid rows are left out of `errors`.
+    pub omitted_errors: u64,
+}
+
+#[derive(serde::Serialize)]
+pub struct RowError {
+    /// The 1-based line the row starts on, the header's being 1, so that it
+    /// matches what an editor shows even with blank lines in between.
+    pub row: u64,
+    pub message: String,
+}
+
+/// Position of each supported column in the uploaded file.
+#[derive(Debug)]
+struct Columns {
+    email: usize,
+    name: usize,
+    status: Option<usize>,
+    tags: Option<usize>,
+}
+
+struct ImportConsent<'a> {
+    context: ConsentContext,
+    consent_text_version: &'a str,
+}
+
+/// Writes the imported rows in transactions of `IMPORT_BATCH_SIZE` rows, so
+/// that a large file does not hold a single transaction open for the whole
+/// upload. A dry run rolls every batch back instead of committing it.
+struct ImportBatches<'a> {
+    pool: &'a PgPool,
+    dry_run: bool,
+    transaction: Option<Transaction<'static, Postgres>>,
+    rows: usize,
+}
+
+
+/// Rows are committed in batches: if the import fails partway, the batches
+/// before the failure stay imported. Confirmation emails are queued for the
+/// worker rather than sent within the request.
+#[tracing::instrument(
+    name = "Import subscribers from CSV",
+    skip(query, payload, pool, consent_text_version),
+    fields(user_id = %*user_id, dry_run = query.dry_run, send_confirmation = query.send_confirmation)
+)]
+pub async fn import_subscribers(
+    query: web::Query<ImportQuery>,
+    mut payload: web::Payload,
+    pool: web::Data<PgPool>,
+    consent_text_version: web::Data<ConsentTextVersion>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, AdminApiError> {
+    let mut report = ImportReport {
+        dry_run: query.dry_run,
+        imported: 0,
+        confirmation_emails_queued: 0,
+        errors: Vec::new(),
+        omitted_errors: 0,
+    };
+    let mut decoder = CsvDecoder::new();
+    let mut records = Vec::new();
+    let mut columns = None;
+    // Duplicates within the file are caught here: the unique index cannot see
+    // the rows of the batches a dry run has already rolled back.
+    let mut seen_emails = HashSet::new();
+    // Imported subscribers consented elsewhere: the events record who
+    // imported them, and when.
+    let consent = ImportConsent {
+        context: ConsentContext::recorded_by(**user_id),
+        consent_text_version: &consent_text_version.0,
+    };
+    let mut batches = ImportBatches {
+        pool: &pool,
+        dry_run: query.dry_run,
+        transaction: None,
+        rows: 0,
+    };
+
+    loop {
+        let chunk = payload.next().await;
+        match &chunk {
+            Some(chunk) => {
+                let chunk = chunk
+                    .as_ref()
+                    .map_err(|e| AdminApiError::ValidationError(format!("Failed to read the upload: {}", e)))?;
+                decoder.feed(chunk, &mut records);
+            },
+            None => decoder.finish(&mut records),
+        }
+
+        for DecodedRecord { line: row, fields } in records.drain(..) {
+            let record = match fields {
+                Ok(record) => record,
+                Err(message) => {
+                    report.push_error(RowError { row, message });
+                    continue;
+                }
+            };
+            let columns = match &columns {
+                Some(columns) => columns,
+                None => {
+                    columns = Some(Columns::from_header(&record).map_err(AdminApiError::ValidationError)?);
+                    continue;
+                }
+            };
+            let outcome = import_record(
+                &record,
+                columns,
+                query.send_confirmation,
+                &consent,
+                &mut seen_emails,
+                batches.transaction().await?,
+            )
+            .await?;
+            match outcome {
+                Ok(confirmation_queued) => {
+                    report.imported += 1;
+                    if confirmation_queued {
+                        report.confirmation_emails_queued += 1;
+                    }
+                },
+                Err(message) => report.push_error(RowError { row, message }),
+            }
+            batches.row_done().await?;
+        }
+
+        if chunk.is_none() {
+            break;
+        }
+    }
+
+    if columns.is_none() {
+        return Err(AdminApiError::ValidationError("The uploaded file is empty".into()));
+    }
+    batches.finish().await?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+impl ImportReport {
+    fn push_error(&mut self, error: RowError) {
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(error);
+        } else {
+            self.omitted_errors += 1;
+        }
+    }
+}
+
+impl ImportBatches<'_> {
+    async fn transaction(&mut self) -> Result<&mut Transaction<'static, Postgres>, anyhow::Error> {
+        let transaction = match self.transaction.take() {
+            Some(transaction) => transaction,
+            None => self.pool
+                .begin()
+                .await
+                .context("Failed to acquire a Postgres connection from the pool")?,
+        };
+        Ok(self.transaction.insert(transaction))
+    }
+
+    async fn row_done(&mut self) -> Result<(), anyhow::Error> {
+        self.rows += 1;
+        if self.rows >= IMPORT_BATCH_SIZE {
+            self.finish().await?;
+        }
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<(), anyhow::Error> {
+        self.rows = 0;
+        match self.transaction.take() {
+            None => {},
+            Some(transaction) if self.dry_run => transaction
+                .rollback()
+                .await
+                .context("Failed to roll back SQL transaction of a dry-run import")?,
+            Some(transaction) => transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to import subscribers")?,
+        }
+        Ok(())
+    }
+}
+
+/// The outer `Result` aborts the whole import, the inner one only the row.
+/// Returns whether a confirmation email was queued.
+async fn import_record(
+    record: &[String],
+    columns: &Columns,
+    send_confirmation: bool,
+    consent: &ImportConsent<'_>,
+    seen_emails: &mut HashSet<String>,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Result<bool, String>, AdminApiError> {
+    let field = |index: usize| record.get(index).map(|s| s.trim()).unwrap_or_default();
+
+    let form = FormData {
+        email: field(columns.email).to_string(),
+        name: field(columns.name).to_string(),
+        ..Default::default()
+    };
+    let new_subscriber = match NewSubscriber::try_from(form) {
+        Ok(new_subscriber) => new_subscriber,
+        Err(e) => return Ok(Err(e.to_string())),
+    };
+    let status = match columns.status.map(field).filter(|s| !s.is_empty()) {
+        None => SubscriptionStatus::PendingConfirmation,
+        Some(status) => match SubscriptionStatus::try_from(status.to_string()) {
+            Ok(status) => status,
+            Err(e) => return Ok(Err(e)),
+        }
+    };
+    let tags: Vec<String> = columns.tags
+        .map(field)
+        .unwrap_or_default()
+        .split(TAG_SEPARATOR)
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+
+    if !seen_emails.insert(new_subscriber.email.as_ref().to_lowercase()) {
+        return Ok(Err(format!("{} appears more than once in the file", new_subscriber.email)));
+    }
+    let subscriber_id = insert_imported_subscriber(&new_subscriber, status, &tags, transaction)
+        .await
+        .context("Failed to insert an imported subscriber in the database")?;
+    let subscriber_id = match subscriber_id {
+        Some(subscriber_id) => subscriber_id,
+        None => return Ok(Err(format!("{} is already subscribed", new_subscriber.email))),
+    };
+    record_subscribed_event(subscriber_id, &consent.context, CONSENT_SOURCE, consent.consent_text_version, transaction)
+        .await
+        .context("Failed to record the consent of an imported subscriber")?;
+    if status == SubscriptionStatus::Confirmed {
+        record_confirmed_event(subscriber_id, &consent.context, CONSENT_SOURCE, consent.consent_text_version, transaction)
+            .await
+            .context("Failed to record the consent of an imported subscriber")?;
+    }
+
+    if !send_confirmation || status != SubscriptionStatus::PendingConfirmation {
+        return Ok(Ok(false));
+    }
+    let subscription_token = generate_subscriptions_token();
+    store_token(subscriber_id, &subscription_token, transaction)
+        .await
+        .context("Failed to store the confirmation token for an imported subscriber")?;
+    enqueue_confirmation_email(subscriber_id, &subscription_token, transaction)
+        .await
+        .context("Failed to queue the confirmation email for an imported subscriber")?;
+
+    Ok(Ok(true))
+}
+
+#[tracing::instrument(
+    name = "Saving imported subscriber in the database",
+    skip(new_subscriber, tags, transaction)
+)]
+async fn insert_imported_subscriber(
+    new_subscriber: &NewSubscriber,
+    status: SubscriptionStatus,
+    tags: &[String],
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status, tags)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT ((lower(email))) DO NOTHING
+        RETURNING id
+        "#,
+        Uuid::new_v4(),
+        new_subscriber.email.as_ref(),
+        new_subscriber.name.as_ref(),
+        Utc::now(),
+        status.as_str(),
+        tags,
+    )
+    .fetch_optional(transaction)
+    .await?;
+
+    Ok(row.map(|r| r.id))
+}
+
+#[tracing::instrument(
+    name = "Export subscribers as CSV",
+    skip(query, pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn export_subscribers(
+    query: web::Query<ExportQuery>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, AdminApiError> {
+    let status = query.0.status
+        .map(SubscriptionStatus::try_from)
+        .transpose()
+        .map_err(AdminApiError::ValidationError)?;
+
+    let header = write_csv_rows(std::iter::once(
+        ["email", "name", "status", "tags", "subscribed_at"].map(String::from)
+    ))?;
+    // The first batch is fetched before answering, so that failing to read
+    // the list is an error status rather than an empty file.
+    let first = next_export_chunk(status, None, &pool).await?;
+    let (first, cursor) = match first {
+        Some((chunk, next)) => (Some(chunk), next),
+        None => (None, None),
+    };
+    let pool = pool.into_inner();
+    // Keyset pagination keeps memory bounded regardless of the list size and
+    // gives the stream ownership of everything it needs.
+    let rest = stream::unfold(cursor, move |after: Option<Uuid>| {
+        let pool = pool.clone();
+        async move {
+            let after = after?;
+            match next_export_chunk(status, Some(after), &pool).await {
+                Ok(chunk) => chunk.map(|(chunk, next)| (Ok::<_, Infallible>(chunk), next)),
+                // The status is already sent: the last line says the file is
+                // incomplete, rather than the download silently stopping.
+                Err(e) => {
+                    tracing::error!(error.cause_chain = ?e, "Failed to fetch a batch of subscribers to export");
+                    Some((Ok(web::Bytes::from_static(EXPORT_FAILED_MARKER.as_bytes())), None))
+                },
+            }
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("subscribers.csv".into())],
+        })
+        .streaming(stream::iter([Ok(header), Ok(first.unwrap_or_default())]).chain(rest)))
+}
+
+/// The CSV rows of the batch after `after`, and the cursor of the next one.
+/// `None` once there are no more subscribers.
+async fn next_export_chunk(
+    status: Option<SubscriptionStatus>,
+    after: Option<Uuid>,
+    pool: &PgPool,
+) -> Result<Option<(web::Bytes, Option<Uuid>)>, anyhow::Error> {
+    let batch = get_export_batch(status, after, pool).await?;
+    if batch.is_empty() {
+        return Ok(None);
+    }
+    let next = if (batch.len() as i64) < EXPORT_BATCH_SIZE {
+        None
+    } else {
+        batch.last().map(|s| s.id)
+    };
+    let rows = batch.into_iter().map(|s| [
+        s.email,
+        s.name,
+        s.status,
+        s.tags.join(&TAG_SEPARATOR.to_string()),
+        s.subscribed_at.to_rfc3339(),
+    ]);
+    Ok(Some((write_csv_rows(rows)?, next)))
+}
+
+struct ExportedSubscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    tags: Vec<String>,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(
+    name = "Get a batch of subscribers to export",
+    skip(pool)
+)]
+async fn get_export_batch(
+    status: Option<SubscriptionStatus>,
+    after: Option<Uuid>,
+    pool: &PgPool,
+) -> Result<Vec<ExportedSubscriber>, anyhow::Error> {
+    let batch = sqlx::query_as!(
+        ExportedSubscriber,
+        r#"
+        SELECT id, email, name, status, tags, subscribed_at
+        FROM subscriptions
+        WHERE ($1::TEXT IS NULL OR status = $1)
+            AND ($2::UUID IS NULL OR id > $2)
+        ORDER BY id
+        LIMIT $3
+        "#,
+        status.map(|s| s.as_str()),
+        after,
+        EXPORT_BATCH_SIZE,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(batch)
+}
+
+fn write_csv_rows<I, R>(rows: I) -> Result<web::Bytes, anyhow::Error>
+where
+    I: IntoIterator<Item = R>,
+    R: IntoIterator<Item = String>,
+{
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        writer.write_record(row)?;
+    }
+    let bytes = writer.into_inner().context("Failed to flush CSV rows")?;
+    Ok(web::Bytes::from(bytes))
+}
+
+impl Columns {
+    fn from_header(header: &[String]) -> Result<Self, String> {
+        let position = |column: &str| header
+            .iter()
+            .position(|h| h.trim().eq_ignore_ascii_case(column));
+
+        Ok(Self {
+            email: position("email").ok_or("The CSV header has no `email` column")?,
+            name: position("name").ok_or("The CSV header has no `name` column")?,
+            status: position("status"),
+            tags: position("tags"),
+        })
+    }
+}
+
+/// Incremental CSV parser: request body chunks can split a record (or a
+/// quoted field) anywhere, so the partially parsed record is kept between
+/// calls to `feed`.
+struct CsvDecoder {
+    reader: csv_core::Reader,
+    output: Vec<u8>,
+    output_len: usize,
+    ends: Vec<usize>,
+    ends_len: usize,
+    /// The `\n` consumed so far, and whether the last byte consumed was one,
+    /// to tell which line a record ends on.
+    newlines: u64,
+    after_newline: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct DecodedRecord {
+    /// 1-based, where the record starts.
+    line: u64,
+    fields: Result<Vec<String>, String>,
+}
+
+impl CsvDecoder {
+    fn new() -> Self {
+        Self {
+            reader: csv_core::Reader::new(),
+            output: vec![0; 1024],
+            output_len: 0,
+            ends: vec![0; 16],
+            ends_len: 0,
+            newlines: 0,
+            after_newline: false,
+        }
+    }
+
+    fn feed(&mut self, input: &[u8], records: &mut Vec<DecodedRecord>) {
+        self.read(input, false, records)
+    }
+
+    /// Flushes a last record that is not followed by a line terminator.
+    fn finish(&mut self, records: &mut Vec<DecodedRecord>) {
+        self.read(&[], true, records)
+    }
+
+    fn read(&mut self, mut input: &[u8], eof: bool, records: &mut Vec<DecodedRecord>) {
+        loop {
+            // `csv_core` takes an empty input as the end of the data.
+            if input.is_empty() && !eof {
+                return;
+            }
+            let (result, n_in, n_out, n_ends) = self.reader.read_record(
+                input,
+                &mut self.output[self.output_len..],
+                &mut self.ends[self.ends_len..],
+            );
+            let consumed = &input[..n_in];
+            self.newlines += consumed.iter().filter(|&&b| b == b'\n').count() as u64;
+            if let Some(&last) = consumed.last() {
+                self.after_newline = last == b'\n';
+            }
+            input = &input[n_in..];
+            self.output_len += n_out;
+            self.ends_len += n_ends;
+
+            match result {
+                ReadRecordResult::InputEmpty | ReadRecordResult::End => return,
+                ReadRecordResult::OutputFull => {
+                    let len = self.output.len();
+                    self.output.resize(len * 2, 0);
+                },
+                ReadRecordResult::OutputEndsFull => {
+                    let len = self.ends.len();
+                    self.ends.resize(len * 2, 0);
+                },
+                ReadRecordResult::Record => {
+                    let record = self.take_record();
+                    if !matches!(&record.fields, Ok(fields) if fields.iter().all(|f| f.is_empty())) {
+                        records.push(record);
+                    }
+                },
+            }
+        }
+    }
+
+    fn take_record(&mut self) -> DecodedRecord {
+        // Quoted fields keep their line breaks, which moves the start of
+        // the record up from the line it ends on.
+        let output = &self.output[..self.output_len];
+        let embedded_newlines = output.iter().filter(|&&b| b == b'\n').count() as u64;
+        let line = self.newlines + 1 - u64::from(self.after_newline) - embedded_newlines;
+
+        let mut start = 0;
+        let fields = self.ends[..self.ends_len]
+            .iter()
+            .map(|&end| {
+                let field = String::from_utf8(self.output[start..end].to_vec());
+                start = end;
+                field
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| "The row is not valid UTF-8".to_string());
+        self.output_len = 0;
+        self.ends_len = 0;
+        DecodedRecord { line, fields }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::assert_err;
+
+    fn decode(chunks: &[&[u8]]) -> Vec<Result<Vec<String>, String>> {
+        decode_records(chunks).into_iter().map(|r| r.fields).collect()
+    }
+
+    fn decode_records(chunks: &[&[u8]]) -> Vec<DecodedRecord> {
+        let mut decoder = CsvDecoder::new();
+        let mut records = Vec::new();
+        for chunk in chunks {
+            decoder.feed(chunk, &mut records);
+        }
+        decoder.finish(&mut records);
+        records
+    }
+
+    #[test]
+    fn records_split_across_chunks_are_reassembled() {
+        let records = decode(&[b"email,na", b"me\nursula@gmail.com,\"le ", b"guin\"\n"]);
+
+        assert_eq!(records, vec![
+            Ok(vec!["email".to_string(), "name".to_string()]),
+            Ok(vec!["ursula@gmail.com".to_string(), "le guin".to_string()]),
+        ]);
+    }
+
+    #[test]
+    fn a_last_record_without_line_terminator_is_not_lost() {
+        let records = decode(&[b"email,name\nursula@gmail.com,le guin"]);
+
+        assert_eq!(records.len(), 2);
+    }
+
+    #[test]
+    fn blank_lines_are_skipped() {
+        let records = decode(&[b"email,name\n\n\nursula@gmail.com,le guin\n\n"]);
+
+        assert_eq!(records.len(), 2);
+    }
+
+    #[test]
+    fn records_are_numbered_by_the_line_they_start_on() {
+        let records = decode_records(&[
+            b"email,name\n\nursula@gmail.com,\"le\ngu",
+            b"in\"\r\n\r\ntolkien@gmail.com,tolkien",
+        ]);
+
+        let lines: Vec<_> = records.iter().map(|r| r.line).collect();
+        assert_eq!(lines, vec![1, 3, 6]);
+    }
+
+    #[test]
+    fn long_fields_grow_the_output_buffer() {
+        let name = "a".repeat(5000);
+        let input = format!("email,name\nursula@gmail.com,{}\n", name);
+        let records = decode(&[input.as_bytes()]);
+
+        assert_eq!(records[1], Ok(vec!["ursula@gmail.com".to_string(), name]));
+    }
+
+    #[test]
+    fn invalid_utf8_is_reported_per_row() {
+        let records = decode(&[b"email,name\nursula@gmail.com,\xff\xfe\n"]);
+
+        assert_err!(&records[1]);
+    }
+
+    #[test]
+    fn a_header_without_email_column_is_rejected() {
+        let header = vec!["name".to_string(), "status".to_string()];
+
+        assert_err!(Columns::from_header(&header));
+    }
+}
@@ -0,0 +1,402 @@
+use std::fmt::Debug;
+use actix_web::{web, HttpResponse, ResponseError, http::StatusCode};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{unlock_account, Role, UserId};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::routes::{api_error_response, error_chain_fmt, send_invitation_email, ApiError};
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+
+
+/// How long an invitation can be accepted for.
+const INVITATION_VALIDITY_HOURS: i64 = 72;
+
+
+#[derive(serde::Deserialize)]
+pub struct SetRoleBody {
+    role: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct InviteUserBody {
+    email: String,
+    role: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct User {
+    pub user_id: Uuid,
+    pub username: String,
+    pub email: Option<String>,
+    pub role: String,
+    pub created_at: DateTime<Utc>,
+    pub disabled_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct Invitation {
+    pub id: Uuid,
+    pub email: String,
+    pub role: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct LockedAccount {
+    pub user_id: Uuid,
+    pub username: String,
+    pub locked_until: DateTime<Utc>,
+}
+
+#[derive(thiserror::Error)]
+pub enum UserAdminError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("There is no user with the provided id")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for UserAdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(f, self)
+    }
+}
+
+impl ResponseError for UserAdminError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UserAdminError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            UserAdminError::NotFound => StatusCode::NOT_FOUND,
+            UserAdminError::Conflict(_) => StatusCode::CONFLICT,
+            UserAdminError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        api_error_response(self)
+    }
+}
+
+impl ApiError for UserAdminError {
+    fn error_code(&self) -> &'static str {
+        match self {
+            UserAdminError::ValidationError(_) => "validation_error",
+            UserAdminError::NotFound => "user_not_found",
+            UserAdminError::Conflict(_) => "conflict",
+            UserAdminError::UnexpectedError(_) => "internal_error",
+        }
+    }
+}
+
+
+#[tracing::instrument(
+    name = "List locked accounts",
+    skip(pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn list_locked_accounts(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, UserAdminError> {
+    let locked_accounts = sqlx::query_as!(
+        LockedAccount,
+        r#"
+        SELECT user_id, username, locked_until AS "locked_until!"
+        FROM users
+        WHERE locked_until > now()
+        ORDER BY locked_until DESC
+        "#,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the locked accounts")?;
+
+    Ok(HttpResponse::Ok().json(locked_accounts))
+}
+
+#[tracing::instrument(
+    name = "Unlock an account",
+    skip(pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn unlock_user(
+    locked_user_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, UserAdminError> {
+    let unlocked = unlock_account(*locked_user_id, &pool)
+        .await
+        .context("Failed to unlock the account")?;
+    if !unlocked {
+        return Err(UserAdminError::NotFound);
+    }
+    tracing::info!(
+        target: "security",
+        security_event = "account_unlocked",
+        unlocked_user_id = %*locked_user_id,
+        "An admin unlocked an account"
+    );
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(
+    name = "Change the role of a user",
+    skip(body, pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn set_user_role(
+    target_user_id: web::Path<Uuid>,
+    body: web::Json<SetRoleBody>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, UserAdminError> {
+    let role = Role::try_from(body.into_inner().role).map_err(UserAdminError::ValidationError)?;
+    // Otherwise the last admin could demote themselves and nobody would be
+    // left to manage users.
+    if *target_user_id == **user_id {
+        return Err(UserAdminError::Conflict("You cannot change your own role".into()));
+    }
+
+    let updated = sqlx::query!(
+        r#"UPDATE users SET role = $2 WHERE user_id = $1"#,
+        *target_user_id,
+        role.as_str(),
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to update the role of the user")?
+    .rows_affected();
+    if updated == 0 {
+        return Err(UserAdminError::NotFound);
+    }
+    tracing::info!(
+        target: "security",
+        security_event = "role_changed",
+        target_user_id = %*target_user_id,
+        %role,
+        "An admin changed the role of a user"
+    );
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(
+    name = "List users",
+    skip(pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn list_users(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, UserAdminError> {
+    let users = sqlx::query_as!(
+        User,
+        r#"
+        SELECT user_id, username, email, role, created_at, disabled_at
+        FROM users
+        ORDER BY username
+        "#,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the users")?;
+
+    Ok(HttpResponse::Ok().json(users))
+}
+
+/// Emails a signed link to the invitee, who then picks their own username
+/// and password. The account only exists once the invitation is accepted.
+#[tracing::instrument(
+    name = "Invite a user",
+    skip(body, pool, email_client, base_url, hmac_secret),
+    fields(user_id = %*user_id)
+)]
+pub async fn invite_user(
+    body: web::Json<InviteUserBody>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, UserAdminError> {
+    let body = body.into_inner();
+    let email = SubscriberEmail::parse(body.email).map_err(|e| UserAdminError::ValidationError(e.to_string()))?;
+    let role = Role::try_from(body.role).map_err(UserAdminError::ValidationError)?;
+
+    let existing = sqlx::query!(
+        r#"SELECT user_id FROM users WHERE lower(email) = lower($1)"#,
+        email.as_ref(),
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to look up users by email")?;
+    if existing.is_some() {
+        return Err(UserAdminError::Conflict("A user with this email already exists".into()));
+    }
+
+    let invitation = sqlx::query_as!(
+        Invitation,
+        r#"
+        INSERT INTO user_invitations (id, email, role, invited_by, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, email, role, expires_at
+        "#,
+        Uuid::new_v4(),
+        email.as_ref(),
+        role.as_str(),
+        **user_id,
+        Utc::now(),
+        Utc::now() + chrono::Duration::hours(INVITATION_VALIDITY_HOURS),
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to store the invitation")?;
+
+    send_invitation_email(
+        &email_client,
+        &email,
+        invitation.id,
+        invitation.expires_at,
+        &base_url.0,
+        &hmac_secret,
+    )
+    .await
+    .context("Failed to send the invitation email")?;
+
+    Ok(HttpResponse::Created().json(invitation))
+}
+
+/// Disabled users can neither log in nor use their sessions or API tokens,
+/// but everything they own is kept in case they are enabled again.
+#[tracing::instrument(
+    name = "Disable a user",
+    skip(pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn disable_user(
+    target_user_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, UserAdminError> {
+    if *target_user_id == **user_id {
+        return Err(UserAdminError::Conflict("You cannot disable yourself".into()));
+    }
+
+    let updated = sqlx::query!(
+        r#"UPDATE users SET disabled_at = COALESCE(disabled_at, now()) WHERE user_id = $1"#,
+        *target_user_id,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to disable the user")?
+    .rows_affected();
+    if updated == 0 {
+        return Err(UserAdminError::NotFound);
+    }
+    tracing::info!(
+        target: "security",
+        security_event = "user_disabled",
+        target_user_id = %*target_user_id,
+        "An admin disabled a user"
+    );
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(
+    name = "Enable a user",
+    skip(pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn enable_user(
+    target_user_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, UserAdminError> {
+    let updated = sqlx::query!(
+        r#"UPDATE users SET disabled_at = NULL WHERE user_id = $1"#,
+        *target_user_id,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to enable the user")?
+    .rows_affected();
+    if updated == 0 {
+        return Err(UserAdminError::NotFound);
+    }
+    tracing::info!(
+        target: "security",
+        security_event = "user_enabled",
+        target_user_id = %*target_user_id,
+        "An admin enabled a user"
+    );
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Deletes a user along with their API tokens and recovery codes.
+#[tracing::instrument(
+    name = "Delete a user",
+    skip(pool),
+    fields(user_id = %*user_id)
+)]
+pub async fn delete_user(
+    target_user_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, UserAdminError> {
+    if *target_user_id == **user_id {
+        return Err(UserAdminError::Conflict("You cannot delete yourself".into()));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        r#"DELETE FROM api_tokens WHERE user_id = $1"#,
+        *target_user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the API tokens of the user")?;
+    sqlx::query!(
+        r#"DELETE FROM recovery_codes WHERE user_id = $1"#,
+        *target_user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the recovery codes of the user")?;
+    let deleted = sqlx::query!(
+        r#"DELETE FROM users WHERE user_id = $1"#,
+        *target_user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the user")?
+    .rows_affected();
+    if deleted == 0 {
+        return Err(UserAdminError::NotFound);
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to delete a user")?;
+    tracing::info!(
+        target: "security",
+        security_event = "user_deleted",
+        target_user_id = %*target_user_id,
+        "An admin deleted a user"
+    );
+
+    Ok(HttpResponse::NoContent().finish())
+}
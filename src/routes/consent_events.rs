@@ -0,0 +1,218 @@
+use actix_web::HttpRequest;
+use actix_web::http::header::USER_AGENT;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::rate_limit::client_ip;
+
+
+/// Where a consent event came from, as seen by the server.
+pub struct ConsentContext {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    /// The admin who recorded the event on the subscriber's behalf.
+    pub recorded_by: Option<Uuid>,
+}
+
+impl ConsentContext {
+    /// The address is the one rate limiting uses, so a client cannot put a
+    /// made-up one on record through `X-Forwarded-For`.
+    pub fn from_request(request: &HttpRequest, trusted_proxy_hops: u32) -> Self {
+        Self {
+            ip_address: client_ip(request, trusted_proxy_hops),
+            user_agent: request
+                .headers()
+                .get(USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string()),
+            recorded_by: None,
+        }
+    }
+
+    /// For events an admin records: the admin's address and user agent say
+    /// nothing about the subscriber, so they are left out.
+    pub fn recorded_by(user_id: Uuid) -> Self {
+        Self {
+            ip_address: None,
+            user_agent: None,
+            recorded_by: Some(user_id),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct ConsentEventRecord {
+    pub event_type: String,
+    pub occurred_at: DateTime<Utc>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub source: String,
+    pub consent_text_version: String,
+    pub recorded_by: Option<Uuid>,
+}
+
+#[tracing::instrument(
+    name = "Record subscribe consent event",
+    skip(context, transaction)
+)]
+pub async fn record_subscribed_event(
+    subscriber_id: Uuid,
+    context: &ConsentContext,
+    source: &str,
+    consent_text_version: &str,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO consent_events
+            (id, subscriber_id, event_type, occurred_at, ip_address, user_agent, source, consent_text_version, recorded_by)
+        VALUES ($1, $2, 'subscribed', $3, $4, $5, $6, $7, $8)
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        Utc::now(),
+        context.ip_address,
+        context.user_agent,
+        source,
+        consent_text_version,
+        context.recorded_by,
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// The confirmation is consent to the text shown at subscription time, so the
+/// version is carried over from the latest `subscribed` event. Subscribers
+/// that predate consent records fall back to `default_consent_text_version`.
+#[tracing::instrument(
+    name = "Record confirm consent event",
+    skip(context, transaction)
+)]
+pub async fn record_confirmed_event(
+    subscriber_id: Uuid,
+    context: &ConsentContext,
+    source: &str,
+    default_consent_text_version: &str,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO consent_events
+            (id, subscriber_id, event_type, occurred_at, ip_address, user_agent, source, consent_text_version, recorded_by)
+        SELECT $1, $2, 'confirmed', $3, $4, $5, $6, COALESCE(
+            (
+                SELECT consent_text_version FROM consent_events
+                WHERE subscriber_id = $2 AND event_type = 'subscribed'
+                ORDER BY occurred_at DESC
+                LIMIT 1
+            ),
+            $7
+        ), $8
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        Utc::now(),
+        context.ip_address,
+        context.user_agent,
+        source,
+        default_consent_text_version,
+        context.recorded_by,
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Keeps the consent events of a subscriber about to be erased, as proof of
+/// their consent and of its withdrawal, but detaches them from the
+/// subscription and drops the address and user agent. A keyed hash of the
+/// email address takes the place of the subscriber, so that the events can
+/// still be found for an address someone brings up. An `erased` event
+/// records the erasure itself.
+#[tracing::instrument(
+    name = "Pseudonymise consent events",
+    skip(email, hmac_secret, transaction)
+)]
+pub async fn pseudonymise_consent_events(
+    subscriber_id: Uuid,
+    email: &str,
+    source: &str,
+    recorded_by: Option<Uuid>,
+    default_consent_text_version: &str,
+    hmac_secret: &Secret<String>,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO consent_events
+            (id, subscriber_id, event_type, occurred_at, source, consent_text_version, recorded_by)
+        SELECT $1, $2, 'erased', $3, $4, COALESCE(
+            (
+                SELECT consent_text_version FROM consent_events
+                WHERE subscriber_id = $2
+                ORDER BY occurred_at DESC
+                LIMIT 1
+            ),
+            $5
+        ), $6
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        Utc::now(),
+        source,
+        default_consent_text_version,
+        recorded_by,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE consent_events
+        SET subscriber_id = NULL, subscriber_hash = $2, ip_address = NULL, user_agent = NULL
+        WHERE subscriber_id = $1
+        "#,
+        subscriber_id,
+        subscriber_hash(email, hmac_secret),
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Addresses are compared case-insensitively, and so are their hashes.
+fn subscriber_hash(email: &str, hmac_secret: &Secret<String>) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(email.to_lowercase().as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[tracing::instrument(
+    name = "Get consent events",
+    skip(pool)
+)]
+pub async fn get_consent_events(
+    subscriber_id: Uuid,
+    pool: &PgPool,
+) -> Result<Vec<ConsentEventRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        ConsentEventRecord,
+        r#"
+        SELECT event_type, occurred_at, ip_address, user_agent, source, consent_text_version, recorded_by
+        FROM consent_events
+        WHERE subscriber_id = $1
+        ORDER BY occurred_at
+        "#,
+        subscriber_id,
+    )
+    .fetch_all(pool)
+    .await
+}
@@ -0,0 +1,250 @@
+use std::fmt::{Display, Formatter};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{self, ContentType, Header};
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use actix_web_lab::middleware::Next;
+
+use crate::request_id::current_request_id;
+
+
+tokio::task_local! {
+    static ERROR_FORMAT: ErrorFormat;
+}
+
+/// How errors are rendered for the request being handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// `application/problem+json` (RFC 7807), for API clients.
+    ProblemJson,
+    /// An error page, for browsers.
+    Html,
+}
+
+impl ErrorFormat {
+    /// Browsers rank `text/html` first. Anything else, including a missing
+    /// `Accept` header or `*/*`, gets problem+json.
+    fn negotiate(request: &ServiceRequest) -> Self {
+        let preferred = header::Accept::parse(request)
+            .ok()
+            .and_then(|accept| accept.ranked().into_iter().find(|mime| {
+                matches!(mime.essence_str(), "text/html" | "application/json" | "application/problem+json")
+            }));
+        match preferred {
+            Some(mime) if mime.essence_str() == "text/html" => ErrorFormat::Html,
+            _ => ErrorFormat::ProblemJson,
+        }
+    }
+}
+
+/// An error the routes return to their callers. Each comes with a
+/// machine-readable code, returned in its body: clients match on these
+/// codes, so they must not change once released, whereas messages may be
+/// reworded at any time.
+pub trait ApiError: ResponseError {
+    fn error_code(&self) -> &'static str;
+
+    /// The fields of a submitted form that were rejected.
+    fn field_errors(&self) -> Option<&FieldErrors> {
+        None
+    }
+}
+
+/// A field of a submitted form and why it was rejected.
+#[derive(Debug, serde::Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+/// Every rejected field of a submitted form, so that they can all be fixed
+/// at once.
+#[derive(Debug, Default, serde::Serialize)]
+#[serde(transparent)]
+pub struct FieldErrors(Vec<FieldError>);
+
+impl FieldErrors {
+    pub fn single(field: &'static str, message: impl Into<String>) -> Self {
+        let mut errors = Self::default();
+        errors.push(field, message);
+        errors
+    }
+
+    pub fn push(&mut self, field: &'static str, message: impl Into<String>) {
+        self.0.push(FieldError { field, message: message.into() });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &FieldError> {
+        self.0.iter()
+    }
+}
+
+impl Display for FieldErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let messages: Vec<&str> = self.0.iter().map(|e| e.message.as_str()).collect();
+        write!(f, "{}", messages.join("; "))
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ProblemDetails<'a> {
+    /// `about:blank`: the title is the status' reason phrase and `code`
+    /// identifies the problem.
+    #[serde(rename = "type")]
+    type_: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<&'a FieldErrors>,
+}
+
+/// Renders `error` as problem+json or as an HTML page, depending on what the
+/// request accepts. The details of server errors stay in the logs, which
+/// the request id leads to.
+pub fn api_error_response<E: ApiError>(error: &E) -> HttpResponse {
+    let status = error.status_code();
+    let detail = if status.is_server_error() {
+        "Something went wrong on our side".to_string()
+    } else {
+        error.to_string()
+    };
+    let problem = ProblemDetails {
+        type_: "about:blank",
+        title: status.canonical_reason().unwrap_or("Error"),
+        status: status.as_u16(),
+        detail,
+        code: error.error_code(),
+        request_id: current_request_id().map(|request_id| request_id.to_string()),
+        errors: error.field_errors().filter(|errors| !errors.is_empty()),
+    };
+
+    let format = ERROR_FORMAT.try_with(|format| *format).unwrap_or(ErrorFormat::ProblemJson);
+    match format {
+        ErrorFormat::ProblemJson => HttpResponse::build(status)
+            .content_type("application/problem+json")
+            .body(serde_json::to_string(&problem).expect("problem details are serializable")),
+        ErrorFormat::Html => HttpResponse::build(status)
+            .content_type(ContentType::html())
+            .body(error_page(&problem)),
+    }
+}
+
+fn error_page(problem: &ProblemDetails<'_>) -> String {
+    let field_errors: String = problem.errors
+        .iter()
+        .flat_map(|errors| errors.iter())
+        .map(|e| format!(
+            "<li><strong>{}</strong>: {}</li>",
+            e.field,
+            htmlescape::encode_minimal(&e.message),
+        ))
+        .collect();
+    let request_id = problem.request_id
+        .as_deref()
+        .map(|id| format!("<p><small>Request id: {}</small></p>", htmlescape::encode_minimal(id)))
+        .unwrap_or_default();
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    <p>{detail}</p>
+    <ul>{field_errors}</ul>
+    {request_id}
+</body>
+</html>"#,
+        title = problem.title,
+        detail = htmlescape::encode_minimal(&problem.detail),
+    )
+}
+
+/// Picks the error format from the `Accept` header. Errors of inner
+/// middleware are rendered here, so that they follow it as well; they stay
+/// errors, since holding on to the request to build a response would keep
+/// the router from recording the matched route.
+pub async fn negotiate_error_format(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let format = ErrorFormat::negotiate(&req);
+    match ERROR_FORMAT.scope(format, next.call(req)).await {
+        Ok(response) => Ok(response.map_into_boxed_body()),
+        Err(e) => {
+            let response = ERROR_FORMAT.sync_scope(format, || e.error_response());
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+/// A request body or query string that could not be deserialized, such as a
+/// form without one of its fields.
+#[derive(thiserror::Error, Debug)]
+#[error("The request is malformed: {0}")]
+pub struct MalformedRequestError(String);
+
+impl ResponseError for MalformedRequestError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        actix_web::http::StatusCode::BAD_REQUEST
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        api_error_response(self)
+    }
+}
+
+impl ApiError for MalformedRequestError {
+    fn error_code(&self) -> &'static str {
+        "malformed_request"
+    }
+}
+
+/// For `FormConfig`, `JsonConfig` and `QueryConfig`, so that extractor
+/// failures are reported like the errors of the routes.
+pub fn malformed_request_handler<E: Display>(error: E, _request: &HttpRequest) -> actix_web::Error {
+    MalformedRequestError(error.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn format_for(accept: Option<&str>) -> ErrorFormat {
+        let mut request = TestRequest::default();
+        if let Some(accept) = accept {
+            request = request.insert_header((header::ACCEPT, accept));
+        }
+        ErrorFormat::negotiate(&request.to_srv_request())
+    }
+
+    #[test]
+    fn browsers_get_html_and_everyone_else_problem_json() {
+        let firefox = "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,*/*;q=0.8";
+        assert_eq!(format_for(Some(firefox)), ErrorFormat::Html);
+        assert_eq!(format_for(Some("application/json, text/html;q=0.5")), ErrorFormat::ProblemJson);
+        assert_eq!(format_for(Some("*/*")), ErrorFormat::ProblemJson);
+        assert_eq!(format_for(None), ErrorFormat::ProblemJson);
+    }
+
+    #[test]
+    fn field_errors_are_collected() {
+        let mut errors = FieldErrors::single("name", "The name is empty or whitespace");
+        errors.push("email", "`x` is not a valid email");
+
+        assert_eq!(errors.iter().map(|e| e.field).collect::<Vec<_>>(), vec!["name", "email"]);
+        assert_eq!(errors.to_string(), "The name is empty or whitespace; `x` is not a valid email");
+    }
+}
@@ -1,6 +1,105 @@
-use actix_web::HttpResponse;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
 
+use crate::rate_limit::RateLimiter;
+use crate::startup::MIGRATOR;
 
+
+/// How long each dependency gets to answer before it is reported as down.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Liveness: the process serves requests, whatever the state of its dependencies.
 pub async fn health_check() -> HttpResponse {
 	HttpResponse::Ok().finish()
 }
+
+#[derive(serde::Serialize)]
+struct Readiness {
+	status: ComponentStatus,
+	components: BTreeMap<&'static str, ComponentHealth>,
+}
+
+#[derive(serde::Serialize)]
+struct ComponentHealth {
+	status: ComponentStatus,
+	latency_ms: u128,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	error: Option<String>,
+}
+
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum ComponentStatus {
+	Up,
+	Down,
+}
+
+/// Readiness: answers 200 only when Postgres, Redis (if configured) and the
+/// migrations are in order, and 503 otherwise, detailing each component.
+#[tracing::instrument(name = "Check readiness", skip(pool, rate_limiter))]
+pub async fn readiness_check(
+	pool: web::Data<PgPool>,
+	rate_limiter: web::Data<RateLimiter>,
+) -> HttpResponse {
+	let mut components = BTreeMap::new();
+	components.insert("database", check(check_database(&pool)).await);
+	components.insert("migrations", check(check_migrations(&pool)).await);
+	if rate_limiter.is_shared() {
+		components.insert("redis", check(rate_limiter.ping()).await);
+	}
+
+	let status = if components.values().all(|c| c.status == ComponentStatus::Up) {
+		ComponentStatus::Up
+	} else {
+		ComponentStatus::Down
+	};
+	let readiness = Readiness { status, components };
+	match status {
+		ComponentStatus::Up => HttpResponse::Ok().json(readiness),
+		ComponentStatus::Down => HttpResponse::ServiceUnavailable().json(readiness),
+	}
+}
+
+async fn check(probe: impl Future<Output = Result<(), anyhow::Error>>) -> ComponentHealth {
+	let started_at = Instant::now();
+	let outcome = match tokio::time::timeout(CHECK_TIMEOUT, probe).await {
+		Ok(outcome) => outcome,
+		Err(_) => Err(anyhow::anyhow!("Timed out after {:?}", CHECK_TIMEOUT)),
+	};
+	let latency_ms = started_at.elapsed().as_millis();
+	match outcome {
+		Ok(()) => ComponentHealth { status: ComponentStatus::Up, latency_ms, error: None },
+		Err(e) => {
+			tracing::warn!(error.cause_chain = ?e, "A dependency is not ready");
+			ComponentHealth { status: ComponentStatus::Down, latency_ms, error: Some(e.to_string()) }
+		},
+	}
+}
+
+async fn check_database(pool: &PgPool) -> Result<(), anyhow::Error> {
+	sqlx::query("SELECT 1")
+		.execute(pool)
+		.await
+		.context("Failed to query Postgres")?;
+	Ok(())
+}
+
+/// Every embedded migration must have been applied successfully.
+async fn check_migrations(pool: &PgPool) -> Result<(), anyhow::Error> {
+	let applied: Vec<i64> = sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+		.fetch_all(pool)
+		.await
+		.context("Failed to read the applied migrations")?;
+	let pending = MIGRATOR
+		.iter()
+		.filter(|migration| !applied.contains(&migration.version))
+		.count();
+	if pending > 0 {
+		anyhow::bail!("{} migrations are pending", pending);
+	}
+	Ok(())
+}
@@ -0,0 +1,284 @@
+use std::fmt::Debug;
+use actix_web::{web, HttpResponse, ResponseError, http::StatusCode};
+use actix_web::http::header::ContentType;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{validate_new_password, PasswordHashing};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::metrics::{record_email, EmailKind};
+use crate::routes::{api_error_response, error_chain_fmt, ApiError, FieldErrors};
+use crate::signed_link::{self, SignatureError};
+use crate::startup::HmacSecret;
+use crate::telemetry::spawn_blocking_with_tracing;
+
+
+const INVITATION_PURPOSE: &str = "user_invitation";
+const MAX_USERNAME_LENGTH: usize = 64;
+
+#[derive(serde::Deserialize)]
+pub struct InvitationParameters {
+    invitation_id: Uuid,
+    expires: i64,
+    signature: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct AcceptInvitationFormData {
+    invitation_id: Uuid,
+    expires: i64,
+    signature: String,
+    username: String,
+    password: Secret<String>,
+    password_check: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum InvitationError {
+    #[error("{0}")]
+    ValidationError(FieldErrors),
+    #[error(transparent)]
+    InvalidLink(#[from] SignatureError),
+    #[error("The invitation has already been accepted or has expired")]
+    Unavailable,
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for InvitationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(f, self)
+    }
+}
+
+impl ResponseError for InvitationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            InvitationError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            InvitationError::InvalidLink(_) => StatusCode::UNAUTHORIZED,
+            InvitationError::Unavailable => StatusCode::GONE,
+            InvitationError::Conflict(_) => StatusCode::CONFLICT,
+            InvitationError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        api_error_response(self)
+    }
+}
+
+impl ApiError for InvitationError {
+    fn error_code(&self) -> &'static str {
+        match self {
+            InvitationError::ValidationError(_) => "validation_error",
+            InvitationError::InvalidLink(_) => "invalid_link",
+            InvitationError::Unavailable => "invitation_unavailable",
+            InvitationError::Conflict(_) => "conflict",
+            InvitationError::UnexpectedError(_) => "internal_error",
+        }
+    }
+
+    fn field_errors(&self) -> Option<&FieldErrors> {
+        match self {
+            InvitationError::ValidationError(errors) => Some(errors),
+            _ => None,
+        }
+    }
+}
+
+
+#[tracing::instrument(
+    name = "Send invitation email",
+    skip(email_client, email, base_url, hmac_secret)
+)]
+pub async fn send_invitation_email(
+    email_client: &EmailClient,
+    email: &SubscriberEmail,
+    invitation_id: Uuid,
+    expires_at: DateTime<Utc>,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+) -> Result<(), reqwest::Error> {
+    let signature = signed_link::sign(&hmac_secret.0, INVITATION_PURPOSE, &invitation_id.to_string(), expires_at);
+    let link = format!(
+        "{}/invitations/accept?invitation_id={}&expires={}&signature={}",
+        base_url,
+        invitation_id,
+        expires_at.timestamp(),
+        signature,
+    );
+
+    let plain_body = format!(
+        "You have been invited to manage our newsletter.\n\
+         Choose a username and password to create your account: {}\n\
+         The link expires on {}.",
+        link,
+        expires_at.format("%Y-%m-%d %H:%M UTC"),
+    );
+    let html_body = format!(
+        "You have been invited to manage our newsletter.<br />\
+         <a href=\"{}\">Create your account</a> by choosing a username and password.<br />\
+         The link expires on {}.",
+        link,
+        expires_at.format("%Y-%m-%d %H:%M UTC"),
+    );
+
+    let outcome = email_client
+        .send_email(email, "You have been invited", &html_body, &plain_body)
+        .await;
+    record_email(EmailKind::Invitation, &outcome);
+    outcome
+}
+
+pub async fn accept_invitation_form(
+    parameters: web::Query<InvitationParameters>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, InvitationError> {
+    parameters.verify(&hmac_secret)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Create your account</title>
+            </head>
+            <body>
+                <form action="/invitations/accept" method="post">
+                    <input type="hidden" name="invitation_id" value="{}">
+                    <input type="hidden" name="expires" value="{}">
+                    <input type="hidden" name="signature" value="{}">
+                    <label>Username
+                        <input type="text" name="username">
+                    </label>
+                    <label>Password
+                        <input type="password" name="password">
+                    </label>
+                    <label>Confirm password
+                        <input type="password" name="password_check">
+                    </label>
+                    <button type="submit">Create account</button>
+                </form>
+            </body>
+            </html>
+            "#,
+            parameters.invitation_id,
+            parameters.expires,
+            htmlescape::encode_attribute(&parameters.signature),
+        )))
+}
+
+/// Creates the account an invitation was sent for. Each invitation can be
+/// accepted once, with the role and email address the admin chose.
+#[tracing::instrument(
+    name = "Accept an invitation",
+    skip(form, pool, hmac_secret, password_hashing),
+    fields(invitation_id = %form.invitation_id, username = %form.username)
+)]
+pub async fn accept_invitation(
+    form: web::Form<AcceptInvitationFormData>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    password_hashing: web::Data<PasswordHashing>,
+) -> Result<HttpResponse, InvitationError> {
+    let form = form.into_inner();
+    let parameters = InvitationParameters {
+        invitation_id: form.invitation_id,
+        expires: form.expires,
+        signature: form.signature,
+    };
+    parameters.verify(&hmac_secret)?;
+    let username = form.username.trim().to_string();
+    if username.is_empty() || username.chars().count() > MAX_USERNAME_LENGTH {
+        return Err(InvitationError::ValidationError(FieldErrors::single(
+            "username",
+            format!("The username must be between 1 and {} characters long", MAX_USERNAME_LENGTH),
+        )));
+    }
+    if form.password.expose_secret() != form.password_check.expose_secret() {
+        return Err(InvitationError::ValidationError(FieldErrors::single("password_check", "The passwords do not match")));
+    }
+    validate_new_password(&form.password)
+        .map_err(|e| InvitationError::ValidationError(FieldErrors::single("password", e)))?;
+    let password = form.password;
+    let password_hashing = password_hashing.into_inner();
+    let password_hash = spawn_blocking_with_tracing(move || password_hashing.hash(password))
+        .await
+        .context("Failed to spawn blocking task.")?
+        .context("Failed to hash password")?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let invitation = sqlx::query!(
+        r#"
+        UPDATE user_invitations
+        SET accepted_at = now()
+        WHERE id = $1 AND accepted_at IS NULL AND expires_at > now()
+        RETURNING email, role
+        "#,
+        parameters.invitation_id,
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to accept the invitation")?
+    .ok_or(InvitationError::Unavailable)?;
+
+    let taken = sqlx::query!(
+        r#"SELECT user_id FROM users WHERE username = $1 OR lower(email) = lower($2)"#,
+        username,
+        invitation.email,
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to check for existing users")?;
+    if taken.is_some() {
+        return Err(InvitationError::Conflict(
+            "The username is taken or an account already exists for this email address".into()
+        ));
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, email, role)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        username,
+        password_hash.expose_secret(),
+        invitation.email,
+        invitation.role,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to create the user")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to accept an invitation")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(r#"<p>Your account has been created. You can now <a href="/login">log in</a>.</p>"#))
+}
+
+impl InvitationParameters {
+    fn verify(&self, hmac_secret: &HmacSecret) -> Result<(), SignatureError> {
+        signed_link::verify(
+            &hmac_secret.0,
+            INVITATION_PURPOSE,
+            &self.invitation_id.to_string(),
+            self.expires,
+            &self.signature,
+        )
+    }
+}
@@ -1,4 +1,4 @@
-use actix_web::{web, HttpResponse, HttpRequest, http::header::ContentType};
+use actix_web::{HttpResponse, HttpRequest, http::header::ContentType};
 
 
 pub async fn login_form(request: HttpRequest) -> HttpResponse {
@@ -7,7 +7,7 @@ pub async fn login_form(request: HttpRequest) -> HttpResponse {
         Some(cookie) => format!("<p><i>{}</i></p>", cookie.value())
     };
     HttpResponse::Ok()
-        .content_type(ContentTcaype::html())
+        .content_type(ContentType::html())
         .body(format!(
             r#"
             <!DOCTYPE html>
@@ -1,17 +1,14 @@
-use crate::authentication::{validate_credentials, Credentials};
+use crate::authentication::{is_two_factor_enabled, Credentials, LoginThrottle};
+use crate::session_state::TypedSession;
+use anyhow::Context;
 use actix_web::http::header::LOCATION;
 use actix_web::HttpResponse;
 use secrecy::Secret;
 use sqlx::PgPool;
 use crate::authentication::AuthError;
 use crate::routes::error_chain_fmt;
-use actix_web::http::StatusCode;
-use actix_web::{web, ResponseError};
-use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest};
 use actix_web::error::InternalError;
-use secrecy::ExposeSecret;
-use hmac::{Hmac, Mac};
-use crate::startup::HmacSecret;
 use actix_web::cookie::Cookie;
 
 
@@ -22,46 +19,80 @@ pub struct FormData {
 }
 
 #[tracing::instrument(
-    skip(form, pool),
+    skip(form, request, pool, login_throttle, session),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 pub async fn login(
     form: web::Form<FormData>,
-    pool: web::Data<PgPool>
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    login_throttle: web::Data<LoginThrottle>,
+    session: TypedSession,
 ) -> Result<HttpResponse, InternalError<LoginError>> {
     let credentials = Credentials {
         username: form.0.username,
         password: form.0.password,
     };
 
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
-    match validate_credentials(credentials, &pool).await {
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    let client_ip = login_throttle.client_ip(&request);
+    match login_throttle.validate_credentials(credentials, client_ip.as_deref(), &pool).await {
         Ok(user_id) => {
-            tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
-            Ok(
-                HttpResponse::SeeOther()
-                .insert_header((LOCATION, "/"))
-                .finish()
-            )
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            start_session(user_id, &pool, session)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))
         },
         Err(e) => {
             let e = match e {
-                AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
-                AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
+                AuthError::Unauthenticated | AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+                AuthError::TooManyAttempts(_) => LoginError::TooManyAttempts(e.into()),
+                AuthError::Forbidden(_) | AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
             };
-            let response = HttpResponse::SeeOther()
-                .insert_header((LOCATION, "/login"))
-                .cookie(Cookie::new("_flash", e.to_string()))
-                .finish();
-            Err(InternalError::from_response(e, response))
+            Err(login_redirect(e))
         }
     }
 }
 
+/// Users with two-factor authentication only get a pending session, which
+/// `/login/two_factor` upgrades once they enter a code.
+async fn start_session(
+    user_id: uuid::Uuid,
+    pool: &PgPool,
+    session: TypedSession,
+) -> Result<HttpResponse, anyhow::Error> {
+    session.renew();
+    let location = if is_two_factor_enabled(user_id, pool).await? {
+        session
+            .insert_pending_user_id(user_id)
+            .context("Failed to store the pending user id in the session")?;
+        "/login/two_factor"
+    } else {
+        session
+            .insert_user_id(user_id)
+            .context("Failed to store the user id in the session")?;
+        "/"
+    };
+
+    Ok(HttpResponse::SeeOther().insert_header((LOCATION, location)).finish())
+}
+
+pub fn login_redirect(e: LoginError) -> InternalError<LoginError> {
+    let response = HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/login"))
+        .cookie(Cookie::new("_flash", e.to_string()))
+        .finish();
+    InternalError::from_response(e, response)
+}
+
 #[derive(thiserror::Error)]
 pub enum LoginError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    #[error("Too many failed attempts, try again later")]
+    TooManyAttempts(#[source] anyhow::Error),
+    #[error("Invalid code")]
+    InvalidSecondFactor,
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -0,0 +1,106 @@
+use actix_web::cookie::Cookie;
+use actix_web::error::InternalError;
+use actix_web::http::header::{ContentType, LOCATION};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use crate::authentication::{AuthError, LoginThrottle};
+use super::post::{login_redirect, LoginError};
+use crate::session_state::TypedSession;
+
+
+#[derive(serde::Deserialize)]
+pub struct TwoFactorFormData {
+    code: Secret<String>,
+}
+
+pub async fn two_factor_form(
+    request: HttpRequest,
+    session: TypedSession,
+) -> Result<HttpResponse, InternalError<LoginError>> {
+    let pending = session
+        .get_pending_user_id()
+        .context("Failed to read the session")
+        .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+    if pending.is_none() {
+        return Ok(HttpResponse::SeeOther().insert_header((LOCATION, "/login")).finish());
+    }
+
+    let error_html = match request.cookie("_flash") {
+        None => "".to_string(),
+        Some(cookie) => format!("<p><i>{}</i></p>", htmlescape::encode_minimal(cookie.value())),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Two-factor authentication</title>
+            </head>
+            <body>
+                {error_html}
+                <form action="/login/two_factor" method="post">
+                    <label>Code from your authenticator app, or a recovery code
+                        <input
+                            type="text"
+                            autocomplete="one-time-code"
+                            name="code"
+                        >
+                    </label>
+                    <button type="submit">Verify</button>
+                </form>
+            </body>
+            </html>
+            "#,
+        )))
+}
+
+#[tracing::instrument(
+    skip(form, pool, login_throttle, session),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn verify_two_factor(
+    form: web::Form<TwoFactorFormData>,
+    pool: web::Data<PgPool>,
+    login_throttle: web::Data<LoginThrottle>,
+    session: TypedSession,
+) -> Result<HttpResponse, InternalError<LoginError>> {
+    let unexpected = |e: anyhow::Error| login_redirect(LoginError::UnexpectedError(e));
+
+    let user_id = match session.get_pending_user_id().context("Failed to read the session").map_err(unexpected)? {
+        Some(user_id) => user_id,
+        None => return Ok(HttpResponse::SeeOther().insert_header((LOCATION, "/login")).finish()),
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    match login_throttle.verify_second_factor(user_id, form.0.code.expose_secret(), &pool).await {
+        Ok(()) => {
+            session.renew();
+            session
+                .insert_user_id(user_id)
+                .context("Failed to store the user id in the session")
+                .map_err(unexpected)?;
+            Ok(HttpResponse::SeeOther().insert_header((LOCATION, "/")).finish())
+        },
+        Err(AuthError::InvalidCredentials(_)) => {
+            let e = LoginError::InvalidSecondFactor;
+            let response = HttpResponse::SeeOther()
+                .insert_header((LOCATION, "/login/two_factor"))
+                .cookie(Cookie::new("_flash", e.to_string()))
+                .finish();
+            Err(InternalError::from_response(e, response))
+        },
+        // The account is locked: the pending login goes, and the password
+        // has to be entered again once the lock is over.
+        Err(e @ AuthError::TooManyAttempts(_)) => {
+            session.log_out();
+            Err(login_redirect(LoginError::TooManyAttempts(e.into())))
+        },
+        Err(e) => Err(unexpected(e.into())),
+    }
+}
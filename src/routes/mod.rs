@@ -4,6 +4,13 @@ mod subscriptions_confirm;
 mod newsletters;
 mod home;
 mod login;
+mod admin;
+mod subscriber_data;
+mod consent_events;
+mod account;
+mod invitations;
+mod password_reset;
+mod errors;
 
 pub use health_check::*;
 pub use subscriptions::*;
@@ -11,4 +18,10 @@ pub use subscriptions_confirm::*;
 pub use newsletters::*;
 pub use home::*;
 pub use login::*;
-
+pub use admin::*;
+pub use subscriber_data::*;
+pub use consent_events::*;
+pub use account::*;
+pub use invitations::*;
+pub use password_reset::*;
+pub use errors::*;
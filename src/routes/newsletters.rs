@@ -2,18 +2,18 @@ use std::fmt::Debug;
 use actix_web::{web, HttpResponse, ResponseError, http::StatusCode, HttpRequest};
 use sqlx::PgPool;
 use anyhow::Context;
-use secrecy::{Secret, ExposeSecret};
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
-use actix_web::http::header::{HeaderMap, HeaderValue};
+use actix_web::http::header::HeaderValue;
 use reqwest::header;
-use sha3::Digest;
-use sqlx::postgres::PgRow;
 // use wiremock::matchers::basic_auth;
 use crate::email_client::EmailClient;
-use crate::routes::error_chain_fmt;
+use crate::metrics::{record_email, EmailKind};
+use crate::routes::{api_error_response, error_chain_fmt, ApiError};
 use crate::domain::SubscriberEmail;
-use crate::telemetry::spawn_blocking_with_tracing;
-use crate::authentication::{validate_credentials, AuthError, Credentials};
+use crate::authentication::{
+    basic_authentication, bearer_token, has_bearer_token, is_two_factor_enabled, load_principal,
+    validate_api_token, AuthError, LoginThrottle, Permission,
+};
+use uuid::Uuid;
 
 
 #[derive(serde::Deserialize)]
@@ -36,6 +36,10 @@ struct ConfirmedSubscriber {
 pub enum PublishError {
     #[error("Authentication failed.")]
     AuthError(#[source] anyhow::Error),
+    #[error("Too many failed attempts, try again later.")]
+    TooManyAttempts(#[source] anyhow::Error),
+    #[error("Publishing is not allowed.")]
+    Forbidden(#[source] anyhow::Error),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -47,24 +51,100 @@ impl Debug for PublishError {
 }
 
 impl ResponseError for PublishError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PublishError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            PublishError::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+            PublishError::Forbidden(_) => StatusCode::FORBIDDEN,
+            PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
     fn error_response(&self) -> HttpResponse {
+        let mut response = api_error_response(self);
+        if let PublishError::AuthError(_) = self {
+            let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, header_value);
+        }
+        response
+    }
+}
+
+impl ApiError for PublishError {
+    fn error_code(&self) -> &'static str {
         match self {
-            PublishError::AuthError(_) => {
-                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
-                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
-                response
-                    .headers_mut()
-                    .insert(header::WWW_AUTHENTICATE, header_value);
-                response
-            },
-            PublishError::UnexpectedError(_) => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
+            PublishError::AuthError(_) => "authentication_failed",
+            PublishError::TooManyAttempts(_) => "too_many_attempts",
+            PublishError::Forbidden(_) => "forbidden",
+            PublishError::UnexpectedError(_) => "internal_error",
         }
     }
 }
 
+/// Publishers authenticate with an API token or, without two-factor
+/// authentication, with their username and password. Either way they need
+/// the `PublishNewsletters` permission: editors and admins have it, and
+/// tokens additionally need the `newsletters:publish` scope.
+async fn authenticate_publisher(
+    request: &HttpRequest,
+    pool: &PgPool,
+    login_throttle: &LoginThrottle,
+) -> Result<Uuid, PublishError> {
+    let auth_error = |e: AuthError| match e {
+        AuthError::Unauthenticated | AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
+        AuthError::TooManyAttempts(_) => PublishError::TooManyAttempts(e.into()),
+        AuthError::Forbidden(_) => PublishError::Forbidden(e.into()),
+        AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+    };
+
+    let (user_id, token_scopes) = if has_bearer_token(request.headers()) {
+        let token = bearer_token(request.headers()).map_err(PublishError::AuthError)?;
+        let grant = validate_api_token(token, pool).await.map_err(auth_error)?;
+        (grant.user_id, Some(grant.scopes))
+    } else {
+        (authenticate_with_password(request, pool, login_throttle).await?, None)
+    };
+
+    load_principal(user_id, token_scopes, pool)
+        .await
+        .and_then(|principal| principal.authorize(Permission::PublishNewsletters))
+        .map_err(auth_error)?;
+
+    Ok(user_id)
+}
+
+async fn authenticate_with_password(
+    request: &HttpRequest,
+    pool: &PgPool,
+    login_throttle: &LoginThrottle,
+) -> Result<Uuid, PublishError> {
+    let auth_error = |e: AuthError| match e {
+        AuthError::TooManyAttempts(_) => PublishError::TooManyAttempts(e.into()),
+        AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+        _ => PublishError::AuthError(e.into()),
+    };
+
+    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    let client_ip = login_throttle.client_ip(request);
+    let user_id = login_throttle
+        .validate_credentials(credentials, client_ip.as_deref(), pool)
+        .await
+        .map_err(auth_error)?;
+    if is_two_factor_enabled(user_id, pool).await? {
+        return Err(PublishError::AuthError(anyhow::anyhow!(
+            "Basic authentication is disabled for accounts with two-factor authentication. Use an API token."
+        )));
+    }
+
+    Ok(user_id)
+}
+
 #[tracing::instrument(
     name = "Publish a newsletter issue",
-    skip(body, pool, email_client, request),
+    skip(body, pool, email_client, request, login_throttle),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 pub async fn publish_newsletter(
@@ -72,31 +152,25 @@ pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
+    login_throttle: web::Data<LoginThrottle>,
 ) -> Result<HttpResponse, PublishError> {
-    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
-
-    let user_id = validate_credentials(credentials, &pool)
-        .await
-        .map_err(|e| match e {
-            AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
-            AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
-        })?;
-    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+    let user_id = authenticate_publisher(&request, &pool, &login_throttle).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
 
     let subscribers = get_confirmed_subscribers(&pool).await?;
     for subscriber in subscribers {
         match subscriber {
             Ok(subscriber) => {
-                email_client
+                let outcome = email_client
                     .send_email(
                         &subscriber.email,
                         &body.title,
                         &body.content.html,
                         &body.content.text,
                     )
-                    .await
-                    .with_context(|| format!("Failed to send newsletter issue to {}", subscriber.email))?;
+                    .await;
+                record_email(EmailKind::Newsletter, &outcome);
+                outcome.with_context(|| format!("Failed to send newsletter issue to {}", subscriber.email))?;
             },
             Err(err) => {
                 tracing::warn!(
@@ -136,33 +210,3 @@ async fn get_confirmed_subscribers(
 
     Ok(confirmed_subscribers)
 }
-
-fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
-    let header_value = headers
-        .get("Authorization")
-        .context("The 'Authorization' header was missing.")?
-        .to_str()
-        .context("The 'Authorization' header was not a valid UTF-8 string.")?;
-    let base64encoded_segment = header_value
-        .strip_prefix("Basic ")
-        .context("The authorization scheme was not 'Basic'.")?;
-    let decoded_bytes = base64::decode_config(base64encoded_segment, base64::STANDARD)
-        .context("Failed to base64-decode 'Basic' credentials.")?;
-    let decoded_credentials = String::from_utf8(decoded_bytes)
-        .context("The decoded credential string is not valid UTF-8.")?;
-
-    let mut credentials = decoded_credentials.splitn(2, ':');
-    let username = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth."))?
-        .to_string();
-    let password = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth."))?
-        .to_string();
-
-    Ok(Credentials {
-        username,
-        password: Secret::new(password)
-    })
-}
\ No newline at end of file
@@ -0,0 +1,346 @@
+use std::fmt::Debug;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError, http::StatusCode};
+use actix_web::http::header::ContentType;
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{change_password, validate_new_password, PasswordHashing};
+use crate::configuration::SubscribeProtectionSettings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::metrics::{record_email, EmailKind};
+use crate::rate_limit::{client_ip, RateLimiter};
+use crate::routes::{api_error_response, error_chain_fmt, ApiError, FieldErrors};
+use crate::startup::ApplicationBaseUrl;
+
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetRequestFormData {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetParameters {
+    token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetFormData {
+    token: String,
+    password: Secret<String>,
+    password_check: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum PasswordResetError {
+    #[error("{0}")]
+    ValidationError(FieldErrors),
+    #[error("Too many password reset requests, try again later")]
+    RateLimited,
+    #[error("The password reset link is invalid, has expired or was already used")]
+    InvalidToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for PasswordResetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(f, self)
+    }
+}
+
+impl ResponseError for PasswordResetError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PasswordResetError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PasswordResetError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            PasswordResetError::InvalidToken => StatusCode::UNAUTHORIZED,
+            PasswordResetError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        api_error_response(self)
+    }
+}
+
+impl ApiError for PasswordResetError {
+    fn error_code(&self) -> &'static str {
+        match self {
+            PasswordResetError::ValidationError(_) => "validation_error",
+            PasswordResetError::RateLimited => "rate_limited",
+            PasswordResetError::InvalidToken => "invalid_token",
+            PasswordResetError::UnexpectedError(_) => "internal_error",
+        }
+    }
+
+    fn field_errors(&self) -> Option<&FieldErrors> {
+        match self {
+            PasswordResetError::ValidationError(errors) => Some(errors),
+            _ => None,
+        }
+    }
+}
+
+
+pub async fn password_reset_request_form() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(
+            r#"
+            <!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Reset your password</title>
+            </head>
+            <body>
+                <form action="/password_reset" method="post">
+                    <label>Email
+                        <input type="email" name="email">
+                    </label>
+                    <button type="submit">Send reset link</button>
+                </form>
+            </body>
+            </html>
+            "#,
+        )
+}
+
+/// Emails a single-use reset link if an active user has this address. The
+/// response is the same either way, so the endpoint cannot be used to find
+/// out who has an account. Requests are rate limited with the limits of
+/// subscriptions, per address and per email, before the lookup.
+#[tracing::instrument(
+    name = "Request a password reset",
+    skip(form, request, pool, email_client, base_url, protection, rate_limiter)
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn request_password_reset(
+    form: web::Form<PasswordResetRequestFormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    protection: web::Data<SubscribeProtectionSettings>,
+    rate_limiter: web::Data<RateLimiter>,
+) -> Result<HttpResponse, PasswordResetError> {
+    if let Some(ip) = client_ip(&request, protection.trusted_proxy_hops) {
+        let key = format!("password_reset:ip:{}", ip);
+        check_rate_limit(&rate_limiter, &key, protection.max_per_ip, &protection).await?;
+    }
+
+    let email = SubscriberEmail::parse(form.0.email)
+        .map_err(|e| PasswordResetError::ValidationError(FieldErrors::single("email", e.to_string())))?;
+    let email_key = format!("password_reset:email:{}", email.as_ref().to_lowercase());
+    check_rate_limit(&rate_limiter, &email_key, protection.max_per_email, &protection).await?;
+
+    let user_id = sqlx::query!(
+        r#"SELECT user_id FROM users WHERE lower(email) = lower($1) AND disabled_at IS NULL"#,
+        email.as_ref(),
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to look up the user")?
+    .map(|r| r.user_id);
+
+    if let Some(user_id) = user_id {
+        let token = store_password_reset_token(user_id, &pool).await?;
+        send_password_reset_email(&email_client, &email, &token, &base_url.0)
+            .await
+            .context("Failed to send the password reset email")?;
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body("<p>If an account uses this address, we sent it a link to reset the password.</p>"))
+}
+
+async fn check_rate_limit(
+    rate_limiter: &RateLimiter,
+    key: &str,
+    limit: u32,
+    protection: &SubscribeProtectionSettings,
+) -> Result<(), PasswordResetError> {
+    if rate_limiter.allows(key, limit, protection.window()).await {
+        Ok(())
+    } else {
+        Err(PasswordResetError::RateLimited)
+    }
+}
+
+pub async fn password_reset_form(
+    parameters: web::Query<PasswordResetParameters>,
+) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Choose a new password</title>
+            </head>
+            <body>
+                <form action="/password_reset/confirm" method="post">
+                    <input type="hidden" name="token" value="{}">
+                    <label>New password
+                        <input type="password" name="password">
+                    </label>
+                    <label>Confirm new password
+                        <input type="password" name="password_check">
+                    </label>
+                    <button type="submit">Change password</button>
+                </form>
+            </body>
+            </html>
+            "#,
+            htmlescape::encode_attribute(&parameters.token),
+        ))
+}
+
+#[tracing::instrument(
+    name = "Reset a password",
+    skip(form, pool, password_hashing)
+)]
+pub async fn reset_password(
+    form: web::Form<PasswordResetFormData>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+) -> Result<HttpResponse, PasswordResetError> {
+    let form = form.into_inner();
+    if form.password.expose_secret() != form.password_check.expose_secret() {
+        return Err(PasswordResetError::ValidationError(FieldErrors::single("password_check", "The passwords do not match")));
+    }
+    validate_new_password(&form.password)
+        .map_err(|e| PasswordResetError::ValidationError(FieldErrors::single("password", e)))?;
+
+    let user_id = consume_password_reset_token(&form.token, &pool)
+        .await?
+        .ok_or(PasswordResetError::InvalidToken)?;
+    change_password(user_id, form.password, &password_hashing, &pool).await?;
+    tracing::info!(
+        target: "security",
+        security_event = "password_reset",
+        %user_id,
+        "A user reset their password"
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(r#"<p>Your password has been changed. You can now <a href="/login">log in</a>.</p>"#))
+}
+
+async fn store_password_reset_token(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Secret<String>, anyhow::Error> {
+    let token = generate_password_reset_token();
+    let created_at = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (token_hash, user_id, created_at, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        hash_password_reset_token(token.expose_secret()),
+        user_id,
+        created_at,
+        created_at + Duration::hours(1),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the password reset token")?;
+
+    Ok(token)
+}
+
+/// Returns the user the token was issued for if it is still valid. Using a
+/// token invalidates every other reset token of the user.
+async fn consume_password_reset_token(
+    token: &str,
+    pool: &PgPool,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let user_id = sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens
+        SET used_at = now()
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+        RETURNING user_id
+        "#,
+        hash_password_reset_token(token),
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to use the password reset token")?
+    .map(|r| r.user_id);
+    if let Some(user_id) = user_id {
+        sqlx::query!(
+            r#"UPDATE password_reset_tokens SET used_at = now() WHERE user_id = $1 AND used_at IS NULL"#,
+            user_id,
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to invalidate the other password reset tokens")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to use a password reset token")?;
+
+    Ok(user_id)
+}
+
+#[tracing::instrument(
+    name = "Send password reset email",
+    skip(email_client, email, token, base_url)
+)]
+async fn send_password_reset_email(
+    email_client: &EmailClient,
+    email: &SubscriberEmail,
+    token: &Secret<String>,
+    base_url: &str,
+) -> Result<(), reqwest::Error> {
+    let link = format!("{}/password_reset/confirm?token={}", base_url, token.expose_secret());
+    let plain_body = format!(
+        "Choose a new password: {}\n\
+         The link expires in an hour. If you did not ask for this, ignore this email.",
+        link,
+    );
+    let html_body = format!(
+        "<a href=\"{}\">Choose a new password</a>.<br />\
+         The link expires in an hour. If you did not ask for this, ignore this email.",
+        link,
+    );
+
+    let outcome = email_client
+        .send_email(email, "Reset your password", &html_body, &plain_body)
+        .await;
+    record_email(EmailKind::PasswordReset, &outcome);
+    outcome
+}
+
+fn generate_password_reset_token() -> Secret<String> {
+    let mut rng = thread_rng();
+    Secret::new(
+        std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(40)
+            .collect()
+    )
+}
+
+/// Reset tokens are long random strings, so a fast hash is enough.
+fn hash_password_reset_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,438 @@
+use std::fmt::Debug;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError, http::StatusCode};
+use actix_web::http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType};
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::configuration::SubscribeProtectionSettings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::metrics::{record_email, EmailKind};
+use crate::rate_limit::{client_ip, RateLimiter};
+use crate::routes::{
+    api_error_response, error_chain_fmt, get_consent_events, pseudonymise_consent_events, ApiError,
+    ConsentEventRecord, FieldErrors,
+};
+use crate::signed_link::{self, SignatureError};
+use crate::startup::{ApplicationBaseUrl, ConsentTextVersion, HmacSecret};
+
+
+const EXPORT_PURPOSE: &str = "data_export";
+const ERASURE_PURPOSE: &str = "data_erasure";
+const ERASURE_SOURCE: &str = "data_request";
+
+#[derive(serde::Deserialize)]
+pub struct DataRequestFormData {
+    email: String,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct SignedParameters {
+    subscriber_id: Uuid,
+    expires: i64,
+    signature: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberDataExport {
+    pub exported_at: DateTime<Utc>,
+    pub subscription: SubscriptionRecord,
+    pub subscription_tokens: Vec<SubscriptionTokenRecord>,
+    pub consent_events: Vec<ConsentEventRecord>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriptionRecord {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub tags: Vec<String>,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriptionTokenRecord {
+    pub subscription_token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum DataRequestError {
+    #[error("{0}")]
+    ValidationError(FieldErrors),
+    #[error("Too many data requests, try again later")]
+    RateLimited,
+    #[error(transparent)]
+    InvalidLink(#[from] SignatureError),
+    #[error("There is no data associated with this link")]
+    UnknownSubscriber,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for DataRequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(f, self)
+    }
+}
+
+impl ResponseError for DataRequestError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DataRequestError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            DataRequestError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            DataRequestError::InvalidLink(_) => StatusCode::UNAUTHORIZED,
+            DataRequestError::UnknownSubscriber => StatusCode::NOT_FOUND,
+            DataRequestError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        api_error_response(self)
+    }
+}
+
+impl ApiError for DataRequestError {
+    fn error_code(&self) -> &'static str {
+        match self {
+            DataRequestError::ValidationError(_) => "validation_error",
+            DataRequestError::RateLimited => "rate_limited",
+            DataRequestError::InvalidLink(_) => "invalid_link",
+            DataRequestError::UnknownSubscriber => "unknown_subscriber",
+            DataRequestError::UnexpectedError(_) => "internal_error",
+        }
+    }
+
+    fn field_errors(&self) -> Option<&FieldErrors> {
+        match self {
+            DataRequestError::ValidationError(errors) => Some(errors),
+            _ => None,
+        }
+    }
+}
+
+
+/// Emails signed export and erasure links to the address, if it is one of
+/// our subscribers. The response is the same either way, so the endpoint
+/// cannot be used to find out who is subscribed. It is rate limited like
+/// subscriptions, since it sends emails too.
+#[tracing::instrument(
+    name = "Request a copy or the erasure of subscriber data",
+    skip(form, request, pool, email_client, base_url, hmac_secret, protection, rate_limiter)
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn request_subscriber_data(
+    form: web::Form<DataRequestFormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    protection: web::Data<SubscribeProtectionSettings>,
+    rate_limiter: web::Data<RateLimiter>,
+) -> Result<HttpResponse, DataRequestError> {
+    if let Some(ip) = client_ip(&request, protection.trusted_proxy_hops) {
+        let key = format!("data_request:ip:{}", ip);
+        check_rate_limit(&rate_limiter, &key, protection.max_per_ip, &protection).await?;
+    }
+
+    let email = SubscriberEmail::parse(form.0.email)
+        .map_err(|e| DataRequestError::ValidationError(FieldErrors::single("email", e.to_string())))?;
+    // Checked before the lookup, so that subscribers and strangers are
+    // limited alike.
+    let email_key = format!("data_request:email:{}", email.as_ref().to_lowercase());
+    check_rate_limit(&rate_limiter, &email_key, protection.max_per_email, &protection).await?;
+
+    let subscriber_id = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE lower(email) = lower($1)"#,
+        email.as_ref(),
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to look up the subscriber")?
+    .map(|r| r.id);
+
+    if let Some(subscriber_id) = subscriber_id {
+        send_data_request_email(&email_client, &email, subscriber_id, &base_url.0, &hmac_secret)
+            .await
+            .context("Failed to send the data request email")?;
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn check_rate_limit(
+    rate_limiter: &RateLimiter,
+    key: &str,
+    limit: u32,
+    protection: &SubscribeProtectionSettings,
+) -> Result<(), DataRequestError> {
+    if rate_limiter.allows(key, limit, protection.window()).await {
+        Ok(())
+    } else {
+        Err(DataRequestError::RateLimited)
+    }
+}
+
+#[tracing::instrument(
+    name = "Send data request email",
+    skip(email_client, email, base_url, hmac_secret)
+)]
+async fn send_data_request_email(
+    email_client: &EmailClient,
+    email: &SubscriberEmail,
+    subscriber_id: Uuid,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+) -> Result<(), reqwest::Error> {
+    let expires_at = Utc::now() + Duration::hours(24);
+    let link = |path: &str, purpose: &str| {
+        let signature = signed_link::sign(&hmac_secret.0, purpose, &subscriber_id.to_string(), expires_at);
+        format!(
+            "{}/subscriptions/data/{}?subscriber_id={}&expires={}&signature={}",
+            base_url,
+            path,
+            subscriber_id,
+            expires_at.timestamp(),
+            signature,
+        )
+    };
+    let export_link = link("export", EXPORT_PURPOSE);
+    let erasure_link = link("erase", ERASURE_PURPOSE);
+
+    let plain_body = format!(
+        "We received a request about the data we hold on you.\n\
+         Download a copy: {}\n\
+         Erase all of it: {}\n\
+         Both links expire in 24 hours. If you did not ask for this, ignore this email.",
+        export_link,
+        erasure_link,
+    );
+    let html_body = format!(
+        "We received a request about the data we hold on you.<br />\
+         <a href=\"{}\">Download a copy</a> or <a href=\"{}\">erase all of it</a>.<br />\
+         Both links expire in 24 hours. If you did not ask for this, ignore this email.",
+        export_link,
+        erasure_link,
+    );
+
+    let outcome = email_client
+        .send_email(email, "Your data", &html_body, &plain_body)
+        .await;
+    record_email(EmailKind::DataExport, &outcome);
+    outcome
+}
+
+#[tracing::instrument(
+    name = "Export subscriber data",
+    skip(parameters, pool, hmac_secret),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn export_subscriber_data(
+    parameters: web::Query<SignedParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, DataRequestError> {
+    parameters.verify(EXPORT_PURPOSE, &hmac_secret)?;
+
+    let export = collect_subscriber_data(parameters.subscriber_id, &pool)
+        .await
+        .context("Failed to collect the subscriber data")?
+        .ok_or(DataRequestError::UnknownSubscriber)?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("subscriber-data.json".into())],
+        })
+        .json(export))
+}
+
+/// The emailed erasure link leads to a confirmation page rather than erasing
+/// straight away: mail scanners and link previews follow `GET` links.
+pub async fn erase_subscriber_data_form(
+    parameters: web::Query<SignedParameters>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, DataRequestError> {
+    parameters.verify(ERASURE_PURPOSE, &hmac_secret)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Erase your data</title>
+            </head>
+            <body>
+                <p>This permanently deletes your subscription and everything we hold about you,
+                except an anonymised record of your consent and of this request.</p>
+                <form action="/subscriptions/data/erase" method="post">
+                    <input type="hidden" name="subscriber_id" value="{}">
+                    <input type="hidden" name="expires" value="{}">
+                    <input type="hidden" name="signature" value="{}">
+                    <button type="submit">Erase my data</button>
+                </form>
+            </body>
+            </html>
+            "#,
+            parameters.subscriber_id,
+            parameters.expires,
+            htmlescape::encode_attribute(&parameters.signature),
+        )))
+}
+
+/// Erases the subscriber, keeping only pseudonymised consent events as proof
+/// of their consent and of its withdrawal.
+#[tracing::instrument(
+    name = "Erase subscriber data",
+    skip(parameters, pool, hmac_secret, consent_text_version),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn erase_subscriber_data(
+    parameters: web::Form<SignedParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    consent_text_version: web::Data<ConsentTextVersion>,
+) -> Result<HttpResponse, DataRequestError> {
+    parameters.verify(ERASURE_PURPOSE, &hmac_secret)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let erased = erase_subscriber(
+        parameters.subscriber_id,
+        ERASURE_SOURCE,
+        None,
+        &consent_text_version.0,
+        &hmac_secret,
+        &mut transaction,
+    )
+        .await
+        .context("Failed to erase the subscriber data")?;
+    if !erased {
+        return Err(DataRequestError::UnknownSubscriber);
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body("<p>Your data has been erased.</p>"))
+}
+
+/// Everything stored about a subscriber, one field per table referencing it.
+#[tracing::instrument(
+    name = "Collect subscriber data",
+    skip(pool)
+)]
+pub async fn collect_subscriber_data(
+    subscriber_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<SubscriberDataExport>, sqlx::Error> {
+    let subscription = sqlx::query_as!(
+        SubscriptionRecord,
+        r#"
+        SELECT id, email, name, status, tags, subscribed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(pool)
+    .await?;
+    let subscription = match subscription {
+        Some(subscription) => subscription,
+        None => return Ok(None),
+    };
+
+    let subscription_tokens = sqlx::query_as!(
+        SubscriptionTokenRecord,
+        r#"SELECT subscription_token FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let consent_events = get_consent_events(subscriber_id, pool).await?;
+
+    Ok(Some(SubscriberDataExport {
+        exported_at: Utc::now(),
+        subscription,
+        subscription_tokens,
+        consent_events,
+    }))
+}
+
+/// Hard-deletes every row referencing the subscriber, returning `false` if
+/// there was no such subscriber. Consent events are the exception: they are
+/// pseudonymised rather than deleted, see `pseudonymise_consent_events`.
+/// Tables added later that reference `subscriptions` must be handled here
+/// as well.
+#[tracing::instrument(
+    name = "Erase subscriber",
+    skip(default_consent_text_version, hmac_secret, transaction)
+)]
+pub async fn erase_subscriber(
+    subscriber_id: Uuid,
+    source: &str,
+    recorded_by: Option<Uuid>,
+    default_consent_text_version: &str,
+    hmac_secret: &HmacSecret,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<bool, sqlx::Error> {
+    let subscriber = sqlx::query!(
+        r#"SELECT email FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    let email = match subscriber {
+        Some(subscriber) => subscriber.email,
+        None => return Ok(false),
+    };
+
+    pseudonymise_consent_events(
+        subscriber_id,
+        &email,
+        source,
+        recorded_by,
+        default_consent_text_version,
+        &hmac_secret.0,
+        transaction,
+    )
+    .await?;
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"DELETE FROM subscriptions WHERE id = $1"#,
+        subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    Ok(true)
+}
+
+impl SignedParameters {
+    fn verify(&self, purpose: &str, hmac_secret: &HmacSecret) -> Result<(), SignatureError> {
+        signed_link::verify(
+            &hmac_secret.0,
+            purpose,
+            &self.subscriber_id.to_string(),
+            self.expires,
+            &self.signature,
+        )
+    }
+}
@@ -1,5 +1,5 @@
-use std::fmt::{Debug, Display, Formatter};
-use actix_web::{web, HttpResponse, http::StatusCode};
+use std::fmt::{Debug, Formatter};
+use actix_web::{web, HttpRequest, HttpResponse, http::StatusCode};
 use sqlx::{PgPool, Transaction, Postgres};
 use uuid::Uuid;
 use chrono::Utc;
@@ -7,24 +7,60 @@ use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use anyhow::Context;
 
-use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::challenge::ChallengeVerifier;
+use crate::configuration::SubscribeProtectionSettings;
+use crate::domain::{NewSubscriber, NewSubscriberError, SubscriberEmail, SubscriberName};
 use crate::email_client::EmailClient;
-use crate::startup::ApplicationBaseUrl;
+use crate::email_validation::EmailDomainPolicy;
+use crate::metrics::{record_email, record_subscription_event, EmailKind, SubscriptionEvent};
+use crate::rate_limit::{client_ip, RateLimiter};
+use crate::routes::{api_error_response, record_subscribed_event, ApiError, ConsentContext, FieldErrors};
+use crate::startup::{ApplicationBaseUrl, ConsentTextVersion};
 
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Default)]
 pub struct FormData {
-    email: String,
-    name: String,
+    pub email: String,
+    pub name: String,
+    /// The form or list the subscriber signed up through.
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Honeypot: hidden from humans by the form, so only bots fill it in.
+    #[serde(default)]
+    pub website: Option<String>,
+    /// Token produced by the challenge widget, when one is configured.
+    #[serde(default)]
+    pub challenge_response: Option<String>,
 }
 
+const DEFAULT_SOURCE: &str = "subscribe_form";
+const MAX_CONSENT_FIELD_LENGTH: usize = 100;
+
 impl TryFrom<FormData> for NewSubscriber {
-	type Error = String;
+	type Error = NewSubscriberError;
 
 	fn try_from(value: FormData) -> Result<Self, Self::Error> {
-		let name = SubscriberName::parse(value.name)?;
-		let email = SubscriberEmail::parse(value.email)?;
-		Ok(Self { name, email })
+		match (SubscriberName::parse(value.name), SubscriberEmail::parse(value.email)) {
+			(Ok(name), Ok(email)) => Ok(Self { name, email }),
+			(Err(name), Ok(_)) => Err(NewSubscriberError::Name(name)),
+			(Ok(_), Err(email)) => Err(NewSubscriberError::Email(email)),
+			(Err(name), Err(email)) => Err(NewSubscriberError::Both { name, email }),
+		}
+	}
+}
+
+impl From<NewSubscriberError> for FieldErrors {
+	fn from(e: NewSubscriberError) -> Self {
+		let mut errors = FieldErrors::default();
+		match e {
+			NewSubscriberError::Name(name) => errors.push("name", name.to_string()),
+			NewSubscriberError::Email(email) => errors.push("email", email.to_string()),
+			NewSubscriberError::Both { name, email } => {
+				errors.push("name", name.to_string());
+				errors.push("email", email.to_string());
+			},
+		}
+		errors
 	}
 }
 
@@ -45,7 +81,11 @@ pub fn error_chain_fmt(
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
 	#[error("{0}")]
-	ValidationError(String),
+	ValidationError(FieldErrors),
+	#[error("Too many subscription attempts, try again later")]
+	RateLimited,
+	#[error("The challenge response was missing or rejected")]
+	ChallengeFailed,
 	#[error(transparent)]
 	UnexpectedError(#[from] anyhow::Error),
 }
@@ -60,27 +100,94 @@ impl actix_web::ResponseError for SubscribeError {
 	fn status_code(&self) -> StatusCode {
 		match self {
 			SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+			SubscribeError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+			SubscribeError::ChallengeFailed => StatusCode::FORBIDDEN,
 			SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
 		}
 	}
+
+	fn error_response(&self) -> HttpResponse {
+		api_error_response(self)
+	}
+}
+
+impl ApiError for SubscribeError {
+	fn error_code(&self) -> &'static str {
+		match self {
+			SubscribeError::ValidationError(_) => "validation_error",
+			SubscribeError::RateLimited => "rate_limited",
+			SubscribeError::ChallengeFailed => "challenge_failed",
+			SubscribeError::UnexpectedError(_) => "internal_error",
+		}
+	}
+
+	fn field_errors(&self) -> Option<&FieldErrors> {
+		match self {
+			SubscribeError::ValidationError(errors) => Some(errors),
+			_ => None,
+		}
+	}
 }
 
 
 #[tracing::instrument(
 	name = "Adding a new subscriber",
-	skip(form, pool, email_client, base_url),
+	skip(form, request, pool, email_client, base_url, consent_text_version, protection, rate_limiter, challenge_verifier, email_domain_policy),
 	fields(
 		subscriber_email = %form.email,
 		subscriber_name = %form.name
 	)
 )]
+#[allow(clippy::too_many_arguments)]
 pub async fn subscribe(
-	form: web::Form<FormData>,
+	mut form: web::Form<FormData>,
+	request: HttpRequest,
 	pool: web::Data<PgPool>,
 	email_client: web::Data<EmailClient>,
-	base_url: web::Data<ApplicationBaseUrl>
+	base_url: web::Data<ApplicationBaseUrl>,
+	consent_text_version: web::Data<ConsentTextVersion>,
+	protection: web::Data<SubscribeProtectionSettings>,
+	rate_limiter: web::Data<RateLimiter>,
+	challenge_verifier: Option<web::Data<dyn ChallengeVerifier>>,
+	email_domain_policy: web::Data<EmailDomainPolicy>,
 ) -> Result<HttpResponse, SubscribeError> {
-	let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+	// Bots get the same response as humans so that they do not learn to skip the field.
+	if form.website.as_deref().is_some_and(|v| !v.trim().is_empty()) {
+		tracing::warn!("Ignored a subscription that filled in the honeypot field");
+		return Ok(HttpResponse::Ok().finish());
+	}
+
+	let client_ip = client_ip(&request, protection.trusted_proxy_hops);
+	if let Some(ip) = &client_ip {
+		check_rate_limit(&rate_limiter, &format!("subscribe:ip:{}", ip), protection.max_per_ip, &protection).await?;
+	}
+
+	let challenge_response = form.0.challenge_response.take();
+	let source = consent_field(form.0.source.take(), "source")?
+		.unwrap_or_else(|| DEFAULT_SOURCE.to_string());
+	let new_subscriber = NewSubscriber::try_from(form.0).map_err(|e| SubscribeError::ValidationError(e.into()))?;
+
+	if let Some(verifier) = challenge_verifier {
+		let response = challenge_response
+			.filter(|r| !r.is_empty())
+			.ok_or(SubscribeError::ChallengeFailed)?;
+		let passed = verifier
+			.verify(&response, client_ip.as_deref())
+			.await
+			.context("Failed to verify the challenge response")?;
+		if !passed {
+			return Err(SubscribeError::ChallengeFailed);
+		}
+	}
+
+	email_domain_policy
+		.check(&new_subscriber.email)
+		.await
+		.map_err(|e| SubscribeError::ValidationError(FieldErrors::single("email", e.to_string())))?;
+
+	// Limits how often anyone can make us email a given address.
+	let email_key = format!("subscribe:email:{}", new_subscriber.email.as_ref().to_lowercase());
+	check_rate_limit(&rate_limiter, &email_key, protection.max_per_email, &protection).await?;
 
 	let mut transaction = pool
 		.begin()
@@ -93,10 +200,16 @@ pub async fn subscribe(
 	store_token(new_subscriber_id, &subscription_token, &mut transaction)
 		.await
 		.context("Failed to store the confirmation token for a new subscriber")?;
+	// The version is the one the server shows, whatever the client claims it saw.
+	let context = ConsentContext::from_request(&request, protection.trusted_proxy_hops);
+	record_subscribed_event(new_subscriber_id, &context, &source, &consent_text_version.0, &mut transaction)
+		.await
+		.context("Failed to record the consent of a new subscriber")?;
 	transaction
 		.commit()
 		.await
 		.context("Failed to commit SQL transaction to store a new subscriber")?;
+	record_subscription_event(SubscriptionEvent::Subscribed);
 
 	send_confirmation_email(&email_client, new_subscriber, &base_url.0, &subscription_token)
 		.await
@@ -105,6 +218,29 @@ pub async fn subscribe(
 	Ok(HttpResponse::Ok().finish())
 }
 
+async fn check_rate_limit(
+	rate_limiter: &RateLimiter,
+	key: &str,
+	limit: u32,
+	protection: &SubscribeProtectionSettings,
+) -> Result<(), SubscribeError> {
+	if rate_limiter.allows(key, limit, protection.window()).await {
+		Ok(())
+	} else {
+		Err(SubscribeError::RateLimited)
+	}
+}
+
+fn consent_field(value: Option<String>, field: &'static str) -> Result<Option<String>, SubscribeError> {
+	match value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty()) {
+		Some(v) if v.len() > MAX_CONSENT_FIELD_LENGTH => Err(SubscribeError::ValidationError(FieldErrors::single(
+			field,
+			format!("`{}` is longer than {} characters", field, MAX_CONSENT_FIELD_LENGTH),
+		))),
+		value => Ok(value),
+	}
+}
+
 #[tracing::instrument(
 	name = "Saving new subscriber details in the database",
 	skip(new_subscriber, transaction)
@@ -157,13 +293,15 @@ pub async fn send_confirmation_email(
 	);
 	let subject = "Welcome!";
 
-	email_client.send_email(
+	let outcome = email_client.send_email(
 		&new_subscriber.email,
 		subject,
-		&html_body,
-		&plain_body
+		html_body,
+		plain_body
 	)
-	.await
+	.await;
+	record_email(EmailKind::Confirmation, &outcome);
+	outcome
 }
 
 pub fn generate_subscriptions_token() -> String {
@@ -195,3 +333,28 @@ pub async fn store_token(
 	Ok(())
 }
 		
+
+/// Queues the confirmation email for the worker to send, see
+/// `worker::deliver_queued_confirmation_emails`.
+#[tracing::instrument(
+	name = "Queue a confirmation email",
+	skip(subscription_token, transaction)
+)]
+pub async fn enqueue_confirmation_email(
+	subscriber_id: Uuid,
+	subscription_token: &str,
+	transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), sqlx::Error> {
+	sqlx::query!(
+		r#"
+		INSERT INTO confirmation_email_queue (subscription_token, subscriber_id, enqueued_at, execute_after)
+		VALUES ($1, $2, now(), now())
+		"#,
+		subscription_token,
+		subscriber_id,
+	)
+	.execute(transaction)
+	.await?;
+
+	Ok(())
+}
@@ -1,10 +1,14 @@
-use std::fmt::{Debug, Display, Formatter};
-use actix_web::{web, HttpResponse, http::StatusCode};
-use sqlx::PgPool;
+use std::fmt::{Debug, Formatter};
+use actix_web::{web, HttpRequest, HttpResponse, http::StatusCode};
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 use anyhow::Context;
 
+use crate::configuration::SubscribeProtectionSettings;
+use crate::metrics::{record_subscription_event, SubscriptionEvent};
 use crate::routes::subscriptions::error_chain_fmt;
+use crate::routes::{api_error_response, record_confirmed_event, ApiError, ConsentContext};
+use crate::startup::ConsentTextVersion;
 
 #[derive(serde::Deserialize)]
 pub struct Parameters {
@@ -32,26 +36,60 @@ impl actix_web::ResponseError for ConfirmError {
             ConfirmError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        api_error_response(self)
+    }
+}
+
+impl ApiError for ConfirmError {
+    fn error_code(&self) -> &'static str {
+        match self {
+            ConfirmError::UnknownToken => "unknown_token",
+            ConfirmError::UnexpectedError(_) => "internal_error",
+        }
+    }
 }
 
 
 #[tracing::instrument(
 name = "Confirm a pending subscriber",
-skip(parameters, pool),
+skip(parameters, request, pool, consent_text_version, protection),
 
 )]
 pub async fn confirm(
     parameters: web::Query<Parameters>,
-    pool: web::Data<PgPool>
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    consent_text_version: web::Data<ConsentTextVersion>,
+    protection: web::Data<SubscribeProtectionSettings>,
 ) -> Result<HttpResponse, ConfirmError> {
     let subscriber_id = get_subscriber_id_from_token(&parameters.subscription_token, &pool)
         .await
         .context("Failed to get subscriber id from authorization token")?
         .ok_or(ConfirmError::UnknownToken)?;
 
-    confirm_subscriber(subscriber_id, &pool)
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let confirmed = confirm_subscriber(subscriber_id, &mut transaction)
         .await
         .context("Failed to update the subscriber status to `confirmed`")?;
+    // Links stay valid after use: clicking one again, even after having
+    // unsubscribed since, must not bring the subscription back.
+    if !confirmed {
+        return Ok(HttpResponse::Ok().finish());
+    }
+    let context = ConsentContext::from_request(&request, protection.trusted_proxy_hops);
+    record_confirmed_event(subscriber_id, &context, "confirmation_link", &consent_text_version.0, &mut transaction)
+        .await
+        .context("Failed to record the consent of a confirmed subscriber")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber")?;
+    record_subscription_event(SubscriptionEvent::Confirmed);
 
     Ok(HttpResponse::Ok().finish())
 }
@@ -77,20 +115,25 @@ pub async fn get_subscriber_id_from_token(
     Ok(row.map(|r| r.subscriber_id))
 }
 
+/// Only pending subscribers are confirmed. Returns whether the subscriber was.
 #[tracing::instrument(
 name = "Mark subscriber as confirmed",
-skip(subscriber_id, pool)
+skip(subscriber_id, transaction)
 )]
-pub async fn confirm_subscriber(subscriber_id: Uuid, pool: &PgPool) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+pub async fn confirm_subscriber(
+    subscriber_id: Uuid,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<bool, sqlx::Error> {
+    let confirmed = sqlx::query!(
         r#"
         UPDATE subscriptions SET status = 'confirmed'
-            WHERE id = $1
+            WHERE id = $1 AND status = 'pending_confirmation'
         "#,
         subscriber_id
     )
-    .execute(pool)
-    .await?;
+    .execute(transaction)
+    .await?
+    .rows_affected();
 
-    Ok(())
+    Ok(confirmed > 0)
 }
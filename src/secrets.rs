@@ -0,0 +1,64 @@
+use std::path::{Path, PathBuf};
+use anyhow::Context;
+use secrecy::Secret;
+
+
+/// Somewhere secrets can be looked up by name, such as a directory of mounted
+/// files. Sources backed by a network service, like a Vault server, are
+/// expected to fetch their secrets when they are created, as settings are
+/// loaded synchronously.
+pub trait SecretSource: Send + Sync {
+	/// Returns `Ok(None)` when the source does not hold the secret.
+	fn get(&self, name: &str) -> Result<Option<Secret<String>>, anyhow::Error>;
+}
+
+/// One file per secret, named after it, as Docker and Kubernetes mount them
+/// (`/run/secrets/database_password`).
+pub struct DirectorySecretSource {
+	directory: PathBuf,
+}
+
+impl DirectorySecretSource {
+	pub fn new(directory: impl Into<PathBuf>) -> Self {
+		Self { directory: directory.into() }
+	}
+}
+
+impl SecretSource for DirectorySecretSource {
+	fn get(&self, name: &str) -> Result<Option<Secret<String>>, anyhow::Error> {
+		let path = self.directory.join(name);
+		if !path.is_file() {
+			return Ok(None);
+		}
+		read_secret_file(&path).map(Some)
+	}
+}
+
+/// Reads a secret from a file, without the line break editors and `echo`
+/// leave at its end.
+pub fn read_secret_file(path: &Path) -> Result<Secret<String>, anyhow::Error> {
+	let secret = std::fs::read_to_string(path)
+		.with_context(|| format!("Failed to read the secret in {}", path.display()))?;
+	Ok(Secret::new(secret.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use claim::assert_none;
+	use secrecy::ExposeSecret;
+
+	#[test]
+	fn secrets_are_read_from_files_named_after_them() {
+		let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+		std::fs::create_dir(&directory).unwrap();
+		std::fs::write(directory.join("database_password"), "s3cret\n").unwrap();
+		let source = DirectorySecretSource::new(&directory);
+
+		let secret = source.get("database_password").unwrap().unwrap();
+
+		assert_eq!(secret.expose_secret(), "s3cret");
+		assert_none!(source.get("application_hmac_secret").unwrap());
+		std::fs::remove_dir_all(directory).unwrap();
+	}
+}
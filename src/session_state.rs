@@ -0,0 +1,52 @@
+use std::future::{ready, Ready};
+use actix_session::{Session, SessionExt};
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpRequest};
+use uuid::Uuid;
+
+
+/// The session cookie, with typed accessors for what we keep in it.
+///
+/// A user who passed the password check but still owes a second factor is
+/// only "pending": handlers must not treat them as logged in.
+pub struct TypedSession(Session);
+
+impl TypedSession {
+    const USER_ID_KEY: &'static str = "user_id";
+    const PENDING_USER_ID_KEY: &'static str = "pending_two_factor_user_id";
+
+    pub fn renew(&self) {
+        self.0.renew();
+    }
+
+    pub fn log_out(self) {
+        self.0.purge();
+    }
+
+    pub fn insert_user_id(&self, user_id: Uuid) -> Result<(), serde_json::Error> {
+        self.0.remove(Self::PENDING_USER_ID_KEY);
+        self.0.insert(Self::USER_ID_KEY, user_id)
+    }
+
+    pub fn get_user_id(&self) -> Result<Option<Uuid>, serde_json::Error> {
+        self.0.get(Self::USER_ID_KEY)
+    }
+
+    pub fn insert_pending_user_id(&self, user_id: Uuid) -> Result<(), serde_json::Error> {
+        self.0.remove(Self::USER_ID_KEY);
+        self.0.insert(Self::PENDING_USER_ID_KEY, user_id)
+    }
+
+    pub fn get_pending_user_id(&self) -> Result<Option<Uuid>, serde_json::Error> {
+        self.0.get(Self::PENDING_USER_ID_KEY)
+    }
+}
+
+impl FromRequest for TypedSession {
+    type Error = <Session as FromRequest>::Error;
+    type Future = Ready<Result<TypedSession, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(TypedSession(req.get_session())))
+    }
+}
@@ -0,0 +1,120 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum SignatureError {
+    #[error("The link has expired.")]
+    Expired,
+    #[error("The link signature is invalid.")]
+    Invalid,
+}
+
+/// Signs `subject` for a given `purpose` (e.g. `data_export`) until `expires_at`,
+/// returning a hex encoded HMAC-SHA256 tag to be embedded in an emailed link.
+///
+/// The purpose is part of the signed message so that a link issued for one
+/// action cannot be replayed against another.
+pub fn sign(
+    secret: &Secret<String>,
+    purpose: &str,
+    subject: &str,
+    expires_at: DateTime<Utc>,
+) -> String {
+    let mut mac = new_mac(secret);
+    mac.update(message(purpose, subject, expires_at.timestamp()).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+pub fn verify(
+    secret: &Secret<String>,
+    purpose: &str,
+    subject: &str,
+    expires: i64,
+    signature: &str,
+) -> Result<(), SignatureError> {
+    let signature = hex::decode(signature).map_err(|_| SignatureError::Invalid)?;
+    let mut mac = new_mac(secret);
+    mac.update(message(purpose, subject, expires).as_bytes());
+    mac.verify_slice(&signature).map_err(|_| SignatureError::Invalid)?;
+
+    if expires < Utc::now().timestamp() {
+        return Err(SignatureError::Expired);
+    }
+    Ok(())
+}
+
+fn new_mac(secret: &Secret<String>) -> Hmac<Sha256> {
+    Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size")
+}
+
+fn message(purpose: &str, subject: &str, expires: i64) -> String {
+    format!("{}:{}:{}", purpose, subject, expires)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use claim::assert_ok;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-secret-long-enough-for-the-tests".to_string())
+    }
+
+    #[test]
+    fn a_freshly_signed_link_is_valid() {
+        let expires_at = Utc::now() + Duration::hours(1);
+        let signature = sign(&secret(), "data_export", "subscriber", expires_at);
+
+        assert_ok!(verify(&secret(), "data_export", "subscriber", expires_at.timestamp(), &signature));
+    }
+
+    #[test]
+    fn an_expired_link_is_rejected() {
+        let expires_at = Utc::now() - Duration::seconds(1);
+        let signature = sign(&secret(), "data_export", "subscriber", expires_at);
+
+        assert_eq!(
+            verify(&secret(), "data_export", "subscriber", expires_at.timestamp(), &signature),
+            Err(SignatureError::Expired)
+        );
+    }
+
+    #[test]
+    fn a_link_cannot_be_reused_for_another_purpose() {
+        let expires_at = Utc::now() + Duration::hours(1);
+        let signature = sign(&secret(), "data_export", "subscriber", expires_at);
+
+        assert_eq!(
+            verify(&secret(), "data_erasure", "subscriber", expires_at.timestamp(), &signature),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn extending_the_expiry_invalidates_the_signature() {
+        let expires_at = Utc::now() + Duration::hours(1);
+        let signature = sign(&secret(), "data_export", "subscriber", expires_at);
+        let extended = (expires_at + Duration::days(365)).timestamp();
+
+        assert_eq!(
+            verify(&secret(), "data_export", "subscriber", extended, &signature),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn malformed_signatures_are_rejected() {
+        let expires_at = Utc::now() + Duration::hours(1);
+
+        assert_eq!(
+            verify(&secret(), "data_export", "subscriber", expires_at.timestamp(), "not-hex"),
+            Err(SignatureError::Invalid)
+        );
+    }
+}
@@ -1,14 +1,45 @@
 use actix_web::{web, App, HttpServer, dev::Server};
-use sqlx::PgPool;
+use actix_web::cookie::Key;
+use actix_session::SessionMiddleware;
+use actix_session::storage::CookieSessionStore;
+use sha2::{Digest, Sha512};
+use sqlx::{Connection, PgPool};
 use std::io;
 use std::net::TcpListener;
 use tracing_actix_web::TracingLogger;
+use actix_web_lab::middleware::from_fn;
 use sqlx::postgres::PgPoolOptions;
+use sqlx::migrate::Migrator;
 use secrecy::{Secret, ExposeSecret};
+use std::sync::Arc;
+use anyhow::Context;
 use crate::email_client::EmailClient;
-use crate::configuration::Settings;
+use crate::email_validation::{DnsDomainResolver, DomainResolver, EmailDomainPolicy};
+use crate::configuration::{DatabaseSettings, Settings, SubscribeProtectionSettings};
+use crate::metrics::{metrics, track_requests};
+use crate::request_id::propagate_request_id;
+use crate::rate_limit::RateLimiter;
+use crate::challenge::{ChallengeVerifier, HttpChallengeVerifier};
 
-use crate::routes::{home, confirm, health_check, publish_newsletter, subscribe, login_form, login};
+use crate::authentication::{reject_anonymous_users, require_login, require_permission, LoginThrottle, PasswordHashing, Permission};
+use crate::routes::{home, confirm, health_check, readiness_check, publish_newsletter, subscribe, login_form, login};
+use crate::routes::{two_factor_form, verify_two_factor};
+use crate::routes::{two_factor_settings, enrol_two_factor, confirm_two_factor, disable_two_factor_authentication};
+use crate::routes::{
+	list_subscribers, get_subscriber, update_subscriber, delete_subscriber,
+	mark_subscriber_confirmed, mark_subscriber_unsubscribed, resend_confirmation_email,
+	import_subscribers, export_subscribers, list_consent_events,
+	list_locked_accounts, unlock_user, set_user_role,
+	list_users, invite_user, disable_user, enable_user, delete_user, create_api_token, list_api_tokens, revoke_api_token,
+};
+use crate::routes::{
+	accept_invitation_form, accept_invitation, password_reset_request_form, request_password_reset,
+	password_reset_form, reset_password,
+};
+use crate::routes::{
+	request_subscriber_data, export_subscriber_data, erase_subscriber_data_form, erase_subscriber_data,
+};
+use crate::routes::{malformed_request_handler, negotiate_error_format};
 
 
 pub struct Application {
@@ -18,36 +49,126 @@ pub struct Application {
 
 pub struct ApplicationBaseUrl(pub String);
 
+pub struct ConsentTextVersion(pub String);
+
 #[derive(Clone, serde::Deserialize)]
 pub struct HmacSecret(pub Secret<String>);
 
 
+#[allow(clippy::too_many_arguments)]
 pub fn run(
 	listener: TcpListener,
 	db_pool: PgPool,
 	email_client: EmailClient,
 	base_url: String,
 	hmac_secret: HmacSecret,
+	consent_text_version: String,
+	subscribe_protection: SubscribeProtectionSettings,
+	rate_limiter: Arc<RateLimiter>,
+	login_throttle: LoginThrottle,
+	password_hashing: PasswordHashing,
+	challenge_verifier: Option<Arc<dyn ChallengeVerifier>>,
+	email_domain_policy: EmailDomainPolicy,
 ) -> io::Result<Server> {
 	let db_pool = web::Data::new(db_pool);
 	let email_client = web::Data::new(email_client);
 	let base_url = web::Data::new(ApplicationBaseUrl(base_url));
 	let hmac_secret = web::Data::new(hmac_secret);
+	let consent_text_version = web::Data::new(ConsentTextVersion(consent_text_version));
+	let subscribe_protection = web::Data::new(subscribe_protection);
+	let rate_limiter = web::Data::from(rate_limiter);
+	let login_throttle = web::Data::new(login_throttle);
+	let password_hashing = web::Data::new(password_hashing);
+	let challenge_verifier = challenge_verifier.map(web::Data::from);
+	let email_domain_policy = web::Data::new(email_domain_policy);
+	let session_key = session_key(&hmac_secret);
+	// Browsers drop `Secure` cookies set over plain HTTP, as in local development.
+	let secure_cookies = base_url.0.starts_with("https://");
 
     let server = HttpServer::new(move || {
-        App::new()
+        let app = App::new()
+			.wrap(
+				SessionMiddleware::builder(CookieSessionStore::default(), session_key.clone())
+					.cookie_secure(secure_cookies)
+					.build()
+			)
+			.wrap(from_fn(track_requests))
+			.wrap(from_fn(negotiate_error_format))
+			// Registered before `TracingLogger`, so that it runs inside its span.
+			.wrap(from_fn(propagate_request_id))
 			.wrap(TracingLogger::default())
 			.route("/", web::get().to(home))
             .route("/health_check", web::get().to(health_check))
+			.route("/health/live", web::get().to(health_check))
+			.route("/health/ready", web::get().to(readiness_check))
+			.route("/metrics", web::get().to(metrics))
 			.route("/subscriptions", web::post().to(subscribe))
 			.route("/subscriptions/confirm", web::get().to(confirm))
+			.route("/subscriptions/data_request", web::post().to(request_subscriber_data))
+			.route("/subscriptions/data/export", web::get().to(export_subscriber_data))
+			.route("/subscriptions/data/erase", web::get().to(erase_subscriber_data_form))
+			.route("/subscriptions/data/erase", web::post().to(erase_subscriber_data))
 			.route("/newsletters", web::post().to(publish_newsletter))
 			.route("/login", web::get().to(login_form))
 			.route("/login", web::post().to(login))
+			.route("/login/two_factor", web::get().to(two_factor_form))
+			.route("/login/two_factor", web::post().to(verify_two_factor))
+			.route("/invitations/accept", web::get().to(accept_invitation_form))
+			.route("/invitations/accept", web::post().to(accept_invitation))
+			.route("/password_reset", web::get().to(password_reset_request_form))
+			.route("/password_reset", web::post().to(request_password_reset))
+			.route("/password_reset/confirm", web::get().to(password_reset_form))
+			.route("/password_reset/confirm", web::post().to(reset_password))
+			.service(
+				web::scope("/account")
+					.wrap(from_fn(require_login))
+					.route("/two_factor", web::get().to(two_factor_settings))
+					.route("/two_factor/enrol", web::post().to(enrol_two_factor))
+					.route("/two_factor/confirm", web::post().to(confirm_two_factor))
+					.route("/two_factor/disable", web::post().to(disable_two_factor_authentication))
+			)
+			.service(
+				web::scope("/admin/api")
+					.wrap(from_fn(reject_anonymous_users))
+					.route("/subscribers", web::get().to(list_subscribers).wrap(require_permission(Permission::ReadSubscribers)))
+					.route("/subscribers/import", web::post().to(import_subscribers).wrap(require_permission(Permission::ManageSubscribers)))
+					.route("/subscribers/export", web::get().to(export_subscribers).wrap(require_permission(Permission::ReadSubscribers)))
+					.route("/subscribers/{subscriber_id}", web::get().to(get_subscriber).wrap(require_permission(Permission::ReadSubscribers)))
+					.route("/subscribers/{subscriber_id}", web::patch().to(update_subscriber).wrap(require_permission(Permission::ManageSubscribers)))
+					.route("/subscribers/{subscriber_id}", web::delete().to(delete_subscriber).wrap(require_permission(Permission::ManageSubscribers)))
+					.route("/subscribers/{subscriber_id}/consent_events", web::get().to(list_consent_events).wrap(require_permission(Permission::ReadSubscribers)))
+					.route("/subscribers/{subscriber_id}/confirm", web::post().to(mark_subscriber_confirmed).wrap(require_permission(Permission::ManageSubscribers)))
+					.route("/subscribers/{subscriber_id}/unsubscribe", web::post().to(mark_subscriber_unsubscribed).wrap(require_permission(Permission::ManageSubscribers)))
+					.route("/subscribers/{subscriber_id}/resend_confirmation", web::post().to(resend_confirmation_email).wrap(require_permission(Permission::ManageSubscribers)))
+					.route("/users", web::get().to(list_users).wrap(require_permission(Permission::ManageUsers)))
+					.route("/users/invitations", web::post().to(invite_user).wrap(require_permission(Permission::ManageUsers)))
+					.route("/users/{user_id}", web::delete().to(delete_user).wrap(require_permission(Permission::ManageUsers)))
+					.route("/users/{user_id}/disable", web::post().to(disable_user).wrap(require_permission(Permission::ManageUsers)))
+					.route("/users/{user_id}/enable", web::post().to(enable_user).wrap(require_permission(Permission::ManageUsers)))
+					.route("/users/locked", web::get().to(list_locked_accounts).wrap(require_permission(Permission::ManageUsers)))
+					.route("/users/{user_id}/unlock", web::post().to(unlock_user).wrap(require_permission(Permission::ManageUsers)))
+					.route("/users/{user_id}/role", web::put().to(set_user_role).wrap(require_permission(Permission::ManageUsers)))
+					.route("/tokens", web::get().to(list_api_tokens).wrap(require_permission(Permission::ManageApiTokens)))
+					.route("/tokens", web::post().to(create_api_token).wrap(require_permission(Permission::ManageApiTokens)))
+					.route("/tokens/{token_id}", web::delete().to(revoke_api_token).wrap(require_permission(Permission::ManageApiTokens)))
+			)
 			.app_data(db_pool.clone())
 			.app_data(email_client.clone())
 			.app_data(base_url.clone())
 			.app_data(hmac_secret.clone())
+			.app_data(consent_text_version.clone())
+			.app_data(subscribe_protection.clone())
+			.app_data(rate_limiter.clone())
+			.app_data(login_throttle.clone())
+			.app_data(password_hashing.clone())
+			.app_data(email_domain_policy.clone())
+			.app_data(web::FormConfig::default().error_handler(malformed_request_handler))
+			.app_data(web::JsonConfig::default().error_handler(malformed_request_handler))
+			.app_data(web::QueryConfig::default().error_handler(malformed_request_handler));
+		match &challenge_verifier {
+			Some(challenge_verifier) => app.app_data(challenge_verifier.clone()),
+			None => app,
+		}
     })
     .listen(listener)?
     .run();
@@ -55,22 +176,107 @@ pub fn run(
 	Ok(server)
 }
 
+pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
+	PgPoolOptions::new()
+		.max_connections(configuration.max_connections)
+		.min_connections(configuration.min_connections)
+		.acquire_timeout(configuration.acquire_timeout())
+		.idle_timeout(configuration.idle_timeout())
+		.connect_lazy_with(configuration.with_db())
+}
+
+/// The migrations in `migrations/`, embedded in the binary.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Arbitrary, but shared by every instance that migrates the database.
+const MIGRATIONS_LOCK_KEY: i64 = 0x7a65_726f_3270_726f;
+
+/// Applies the migrations embedded in the binary that the database lacks.
+/// Instances starting at the same time wait for each other on an advisory
+/// lock, so that only the first one applies them.
+#[tracing::instrument(name = "Migrate the database", skip(pool))]
+pub async fn migrate_database(pool: &PgPool) -> Result<(), anyhow::Error> {
+	let mut connection = pool
+		.acquire()
+		.await
+		.context("Failed to acquire a Postgres connection from the pool")?;
+	sqlx::query("SELECT pg_advisory_lock($1)")
+		.bind(MIGRATIONS_LOCK_KEY)
+		.execute(&mut connection)
+		.await
+		.context("Failed to take the migrations lock")?;
+
+	let outcome = MIGRATOR
+		.run(&mut connection)
+		.await
+		.context("Failed to apply the migrations");
+
+	let unlocked = sqlx::query("SELECT pg_advisory_unlock($1)")
+		.bind(MIGRATIONS_LOCK_KEY)
+		.execute(&mut connection)
+		.await;
+	if let Err(e) = unlocked {
+		// The lock belongs to the session, so the connection must not go back
+		// to the pool still holding it: closing it releases the lock. A failed
+		// close drops the connection all the same.
+		let _ = connection.detach().close().await;
+		return Err(anyhow::Error::new(e).context("Failed to release the migrations lock"));
+	}
+	outcome
+}
+
+/// Session cookies are encrypted and signed with a key derived from the
+/// HMAC secret, which may be shorter than the 64 bytes `Key` requires.
+fn session_key(hmac_secret: &HmacSecret) -> Key {
+	Key::from(&Sha512::digest(hmac_secret.0.expose_secret().as_bytes()))
+}
+
 impl Application {
-	pub fn build(configuration: Settings) -> io::Result<Self> {
-		let connection_pool = PgPoolOptions::new()
-			.connect_lazy(&configuration.database.connection_string().expose_secret())
-			.expect("failed to create postgres connection pool");
-
-		let email_client = {
-			let sender_email = configuration.email_client
-				.sender()
-				.expect("invalid sender email address");
-			let timeout = configuration.email_client
-				.timeout();
-			let base_url = configuration.email_client.base_url;
-			let authorization_token = configuration.email_client.authorization_token;
-			EmailClient::new(base_url, sender_email, authorization_token, timeout)
+	pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
+		let connection_pool = get_connection_pool(&configuration.database);
+		if configuration.application.run_migrations {
+			migrate_database(&connection_pool).await?;
+		}
+
+		// `Settings::validate` has checked the settings used below, so these
+		// errors are only expected from settings built without it.
+		let rate_limiter = Arc::new(match &configuration.redis_uri {
+			Some(redis_uri) => RateLimiter::redis(redis_uri)?,
+			None => RateLimiter::in_memory(),
+		});
+		let password_hashing = PasswordHashing::new(&configuration.password_hashing)
+			.context("Failed to set up password hashing")?;
+		let login_throttle = LoginThrottle::new(
+			configuration.login_protection.clone(),
+			password_hashing.clone(),
+			rate_limiter.clone(),
+		);
+
+		// The captcha provider is held to the same timeout as the email API.
+		let challenge_verifier = configuration.subscribe_protection.challenge
+			.clone()
+			.map(|challenge| -> Arc<dyn ChallengeVerifier> {
+				Arc::new(HttpChallengeVerifier::new(
+					challenge.verify_url,
+					challenge.secret_key,
+					configuration.email_client.timeout(),
+				))
+			});
+
+		let domain_resolver = if configuration.subscribe_protection.check_email_domain_dns {
+			let resolver: Arc<dyn DomainResolver> = Arc::new(DnsDomainResolver::from_system_conf()?);
+			Some(resolver)
+		} else {
+			None
 		};
+		let email_domain_policy = EmailDomainPolicy::new(
+			&configuration.subscribe_protection.blocked_email_domains,
+			domain_resolver,
+		);
+
+		let email_client = configuration.email_client
+			.client()
+			.context("Invalid sender email address")?;
 
 		let listener = {
 			let host = configuration.application.host;
@@ -86,6 +292,13 @@ impl Application {
 			email_client,
 			configuration.application.base_url,
 			configuration.application.hmac_secret,
+			configuration.application.consent_text_version,
+			configuration.subscribe_protection,
+			rate_limiter,
+			login_throttle,
+			password_hashing,
+			challenge_verifier,
+			email_domain_policy,
 		)?;
 
 		Ok(Self { port, server })
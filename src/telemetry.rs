@@ -1,16 +1,27 @@
+use opentelemetry::propagation::Injector;
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace::{self, Tracer};
+use opentelemetry::sdk::Resource;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use tracing::subscriber::set_global_default;
 use tracing::Subscriber;
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::{layer::SubscriberExt, fmt::MakeWriter, EnvFilter, Registry};
 use tokio::task::JoinHandle;
 
+use crate::configuration::TelemetrySettings;
+
 
 pub fn get_subscriber<S>(
 	name: String, 
 	env_filter: String,
 	sink: S
-) -> impl Subscriber + Send + Sync 
+) -> impl Subscriber + for<'a> LookupSpan<'a> + Send + Sync
 where S: for<'a> MakeWriter<'a> + Send + Sync + 'static
 {
 	let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
@@ -21,6 +32,77 @@ where S: for<'a> MakeWriter<'a> + Send + Sync + 'static
 		.with(formatting_layer)
 }
 
+/// Exports spans to an OpenTelemetry collector over OTLP/HTTP, when an
+/// endpoint is configured. W3C `traceparent` headers are then honoured on
+/// incoming requests and added to outgoing ones.
+///
+/// Spans are exported in batches from a thread of their own, so that
+/// flushing them on shutdown cannot wait on the single-threaded runtime
+/// actix-web runs on.
+pub fn get_otlp_layer<S>(
+	service_name: &str,
+	settings: &TelemetrySettings,
+) -> Result<Option<OpenTelemetryLayer<S, Tracer>>, anyhow::Error>
+where S: Subscriber + for<'a> LookupSpan<'a>
+{
+	let endpoint = match &settings.otlp_endpoint {
+		Some(endpoint) => endpoint,
+		None => return Ok(None),
+	};
+	opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+	let tracer = opentelemetry_otlp::new_pipeline()
+		.tracing()
+		.with_exporter(
+			opentelemetry_otlp::new_exporter()
+				.http()
+				.with_endpoint(endpoint.as_str())
+				.with_timeout(settings.export_timeout())
+		)
+		.with_trace_config(
+			trace::config()
+				.with_resource(Resource::new(vec![KeyValue::new("service.name", service_name.to_string())]))
+		)
+		.install_batch(opentelemetry::runtime::TokioCurrentThread)?;
+	Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+/// Flushes the spans that are still buffered.
+pub fn shutdown_tracer_provider() {
+	opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Calls `shutdown_tracer_provider` when dropped, so that spans are flushed
+/// on every way out of `main`, early returns included.
+pub struct TracerProviderGuard;
+
+impl Drop for TracerProviderGuard {
+	fn drop(&mut self) {
+		shutdown_tracer_provider();
+	}
+}
+
+/// The `traceparent` (and `tracestate`) headers of the current span, so that
+/// the trace continues in the services we call. Empty when no traces are
+/// exported.
+pub fn trace_context_headers() -> HeaderMap {
+	let mut headers = HeaderMap::new();
+	let context = tracing::Span::current().context();
+	opentelemetry::global::get_text_map_propagator(|propagator| {
+		propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+	});
+	headers
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+	fn set(&mut self, key: &str, value: String) {
+		if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(key.as_bytes()), HeaderValue::from_str(&value)) {
+			self.0.insert(name, value);
+		}
+	}
+}
+
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
 	LogTracer::init().expect("failed to set logger");
 	set_global_default(subscriber).expect("failed to set subscriber");
@@ -34,3 +116,32 @@ pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
 	let current_span = tracing::Span::current();
 	tokio::task::spawn_blocking(move || current_span.in_scope(f))
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use wiremock::matchers::method;
+	use wiremock::{Mock, MockServer, ResponseTemplate};
+
+	#[tokio::test]
+	async fn spans_are_exported_to_the_collector() {
+		let collector = MockServer::start().await;
+		Mock::given(method("POST"))
+			.respond_with(ResponseTemplate::new(200))
+			.mount(&collector)
+			.await;
+		let settings = TelemetrySettings {
+			otlp_endpoint: Some(collector.uri()),
+			export_timeout_milliseconds: 1000,
+		};
+		let subscriber = Registry::default().with(get_otlp_layer("test", &settings).unwrap());
+
+		tracing::subscriber::with_default(subscriber, || {
+			tracing::info_span!("exported").in_scope(|| {});
+		});
+		tokio::task::spawn_blocking(shutdown_tracer_provider).await.unwrap();
+
+		let requests = collector.received_requests().await.unwrap();
+		assert!(!requests.is_empty());
+	}
+}
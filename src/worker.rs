@@ -0,0 +1,202 @@
+use std::time::Duration;
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::configuration::Settings;
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::email_client::EmailClient;
+use crate::routes::send_confirmation_email;
+use crate::startup::get_connection_pool;
+
+
+/// How long the worker waits between two rounds of jobs.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// Failed confirmation emails are retried this many times before being
+/// dropped, waiting `RETRY_BACKOFF` times the attempt number in between.
+const MAX_CONFIRMATION_EMAIL_RETRIES: i16 = 5;
+const RETRY_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// Runs the background jobs, without serving HTTP, until the process is
+/// stopped. Failed rounds are logged and retried on the next one.
+pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+	let pool = get_connection_pool(&configuration.database);
+	let base_url = configuration.application.base_url;
+	let email_client = configuration.email_client
+		.client()
+		.context("Invalid sender email address")?;
+	loop {
+		if let Err(e) = purge_expired_records(&pool).await {
+			tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to purge expired records");
+		}
+		if let Err(e) = deliver_queued_confirmation_emails(&pool, &email_client, &base_url).await {
+			tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to deliver queued confirmation emails");
+		}
+		tokio::time::sleep(POLL_INTERVAL).await;
+	}
+}
+
+/// Deletes password reset tokens that can no longer be used and invitations
+/// that expired without being accepted. Returns how many rows were deleted.
+#[tracing::instrument(name = "Purge expired records", skip(pool))]
+pub async fn purge_expired_records(pool: &PgPool) -> Result<u64, anyhow::Error> {
+	let tokens = sqlx::query!(
+		r#"DELETE FROM password_reset_tokens WHERE used_at IS NOT NULL OR expires_at <= now()"#
+	)
+	.execute(pool)
+	.await
+	.context("Failed to delete expired password reset tokens")?
+	.rows_affected();
+	let invitations = sqlx::query!(
+		r#"DELETE FROM user_invitations WHERE accepted_at IS NULL AND expires_at <= now()"#
+	)
+	.execute(pool)
+	.await
+	.context("Failed to delete expired invitations")?
+	.rows_affected();
+
+	tracing::info!(tokens, invitations, "Purged expired records");
+	Ok(tokens + invitations)
+}
+
+/// Sends the queued confirmation emails that are due, until there are none
+/// left. Returns how many were sent.
+pub async fn deliver_queued_confirmation_emails(
+	pool: &PgPool,
+	email_client: &EmailClient,
+	base_url: &str,
+) -> Result<u64, anyhow::Error> {
+	let mut sent = 0;
+	while let Some(outcome) = try_deliver_confirmation_email(pool, email_client, base_url).await? {
+		if outcome == DeliveryOutcome::Sent {
+			sent += 1;
+		}
+	}
+	Ok(sent)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum DeliveryOutcome {
+	Sent,
+	Failed,
+	Skipped,
+}
+
+/// Locks one due email so that several workers can run side by side, and
+/// removes it from the queue unless it failed and can still be retried.
+#[tracing::instrument(
+	skip_all,
+	fields(subscriber_id = tracing::field::Empty),
+)]
+async fn try_deliver_confirmation_email(
+	pool: &PgPool,
+	email_client: &EmailClient,
+	base_url: &str,
+) -> Result<Option<DeliveryOutcome>, anyhow::Error> {
+	let mut transaction = pool
+		.begin()
+		.await
+		.context("Failed to acquire a Postgres connection from the pool")?;
+	let task = sqlx::query!(
+		r#"
+		SELECT q.subscription_token, q.subscriber_id, q.n_retries, s.email, s.name, s.status
+		FROM confirmation_email_queue q
+		JOIN subscriptions s ON s.id = q.subscriber_id
+		WHERE q.execute_after <= now()
+		ORDER BY q.execute_after
+		FOR UPDATE OF q
+		SKIP LOCKED
+		LIMIT 1
+		"#,
+	)
+	.fetch_optional(&mut transaction)
+	.await
+	.context("Failed to fetch a queued confirmation email")?;
+	let task = match task {
+		Some(task) => task,
+		None => return Ok(None),
+	};
+	tracing::Span::current().record("subscriber_id", tracing::field::display(task.subscriber_id));
+
+	// They may have confirmed through an earlier email in the meantime.
+	let outcome = if task.status != "pending_confirmation" {
+		DeliveryOutcome::Skipped
+	} else {
+		let subscriber = SubscriberEmail::parse(task.email)
+			.map_err(anyhow::Error::from)
+			.and_then(|email| Ok(NewSubscriber { email, name: SubscriberName::parse(task.name)? }));
+		match subscriber {
+			Ok(subscriber) => {
+				match send_confirmation_email(email_client, subscriber, base_url, &task.subscription_token).await {
+					Ok(()) => DeliveryOutcome::Sent,
+					Err(e) => {
+						tracing::warn!(
+							error.cause_chain = ?e,
+							error.message = %e,
+							n_retries = task.n_retries,
+							"Failed to send a queued confirmation email",
+						);
+						DeliveryOutcome::Failed
+					}
+				}
+			},
+			Err(e) => {
+				tracing::error!(
+					error.cause_chain = ?e,
+					error.message = %e,
+					"Skipping a confirmation email to a subscriber whose stored details are invalid",
+				);
+				DeliveryOutcome::Skipped
+			}
+		}
+	};
+
+	if outcome == DeliveryOutcome::Failed && task.n_retries < MAX_CONFIRMATION_EMAIL_RETRIES {
+		retry_later(&task.subscription_token, task.n_retries + 1, &mut transaction).await?;
+	} else {
+		if outcome == DeliveryOutcome::Failed {
+			tracing::error!("Giving up on a confirmation email that failed too many times");
+		}
+		dequeue(&task.subscription_token, &mut transaction).await?;
+	}
+	transaction
+		.commit()
+		.await
+		.context("Failed to commit SQL transaction to update the confirmation email queue")?;
+	Ok(Some(outcome))
+}
+
+async fn retry_later(
+	subscription_token: &str,
+	n_retries: i16,
+	transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), anyhow::Error> {
+	let backoff = RETRY_BACKOFF * n_retries as u32;
+	sqlx::query!(
+		r#"
+		UPDATE confirmation_email_queue
+		SET n_retries = $2, execute_after = now() + make_interval(secs => $3)
+		WHERE subscription_token = $1
+		"#,
+		subscription_token,
+		n_retries,
+		backoff.as_secs_f64(),
+	)
+	.execute(transaction)
+	.await
+	.context("Failed to postpone a queued confirmation email")?;
+	Ok(())
+}
+
+async fn dequeue(
+	subscription_token: &str,
+	transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), anyhow::Error> {
+	sqlx::query!(
+		r#"DELETE FROM confirmation_email_queue WHERE subscription_token = $1"#,
+		subscription_token,
+	)
+	.execute(transaction)
+	.await
+	.context("Failed to remove a confirmation email from the queue")?;
+	Ok(())
+}
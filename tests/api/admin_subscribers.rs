@@ -0,0 +1,388 @@
+use crate::helpers::{spawn_app, TestApp};
+use reqwest::Method;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+
+async fn create_subscriber(app: &TestApp, name: &str, email: &str) -> Uuid {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let body = serde_urlencoded::to_string([("name", name), ("email", email)]).unwrap();
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the new subscriber")
+        .id
+}
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/api/subscribers", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(r#"Basic realm="admin""#, response.headers()["WWW-Authenticate"]);
+}
+
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/api/subscribers", &app.address))
+        .basic_auth(&app.test_user.username, Some(Uuid::new_v4().to_string()))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_status_and_searched() {
+    let app = spawn_app().await;
+    let ursula = create_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    create_subscriber(&app, "tolkien", "tolkien@gmail.com").await;
+    app.admin_api(Method::POST, &format!("/subscribers/{}/confirm", ursula))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let all: serde_json::Value = app.admin_api(Method::GET, "/subscribers")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let confirmed: serde_json::Value = app.admin_api(Method::GET, "/subscribers?status=confirmed")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let searched: serde_json::Value = app.admin_api(Method::GET, "/subscribers?search=TOLK")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(all["total"], 2);
+    assert_eq!(confirmed["total"], 1);
+    assert_eq!(confirmed["subscribers"][0]["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(searched["total"], 1);
+    assert_eq!(searched["subscribers"][0]["name"], "tolkien");
+}
+
+#[tokio::test]
+async fn subscribers_are_paginated() {
+    let app = spawn_app().await;
+    for i in 0..3 {
+        create_subscriber(&app, "reader", &format!("reader{}@gmail.com", i)).await;
+    }
+
+    let page: serde_json::Value = app.admin_api(Method::GET, "/subscribers?page=2&per_page=2")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(page["total"], 3);
+    assert_eq!(page["page"], 2);
+    assert_eq!(page["subscribers"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn invalid_listing_parameters_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("status=deleted", "unknown status"),
+        ("page=0", "page below 1"),
+        ("per_page=1000", "page size too large"),
+    ];
+
+    for (query, error_message) in test_cases {
+        let response = app.admin_api(Method::GET, &format!("/subscribers?{}", query))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the query had {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn unknown_subscribers_return_a_404() {
+    let app = spawn_app().await;
+
+    let response = app.admin_api(Method::GET, &format!("/subscribers/{}", Uuid::new_v4()))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn updates_are_validated_with_the_domain_types() {
+    let app = spawn_app().await;
+    let id = create_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    let test_cases = vec![
+        (serde_json::json!({"name": "<script>"}), "forbidden characters in name"),
+        (serde_json::json!({"email": "definitely-not-an-email"}), "invalid email"),
+    ];
+
+    for (body, error_message) in test_cases {
+        let response = app.admin_api(Method::PATCH, &format!("/subscribers/{}", id))
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload had {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn a_valid_update_is_persisted() {
+    let app = spawn_app().await;
+    let id = create_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+
+    let response = app.admin_api(Method::PATCH, &format!("/subscribers/{}", id))
+        .json(&serde_json::json!({"name": "Ursula K. Le Guin"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!("SELECT email, name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "Ursula K. Le Guin");
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn changing_the_email_of_a_confirmed_subscriber_asks_for_a_new_confirmation() {
+    let app = spawn_app().await;
+    let id = create_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    sqlx::query!("UPDATE subscriptions SET status = 'confirmed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let old_token = sqlx::query!("SELECT subscription_token FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .subscription_token;
+
+    let subscriber: serde_json::Value = app.admin_api(Method::PATCH, &format!("/subscribers/{}", id))
+        .json(&serde_json::json!({"email": "ursula@gmail.com"}))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(subscriber["status"], "pending_confirmation");
+
+    let tokens: Vec<_> = sqlx::query!("SELECT subscription_token FROM subscription_tokens")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.subscription_token)
+        .collect();
+    assert_eq!(tokens.len(), 1);
+    assert_ne!(tokens[0], old_token);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_requests.last().unwrap().body).unwrap();
+    assert_eq!(body["To"], "ursula@gmail.com");
+}
+
+#[tokio::test]
+async fn updating_to_an_email_already_in_use_returns_a_409() {
+    let app = spawn_app().await;
+    let id = create_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    create_subscriber(&app, "tolkien", "tolkien@gmail.com").await;
+
+    let response = app.admin_api(Method::PATCH, &format!("/subscribers/{}", id))
+        .json(&serde_json::json!({"email": "tolkien@gmail.com"}))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn a_subscriber_can_be_unsubscribed_manually() {
+    let app = spawn_app().await;
+    let id = create_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+
+    let subscriber: serde_json::Value = app.admin_api(Method::POST, &format!("/subscribers/{}/unsubscribe", id))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(subscriber["status"], "unsubscribed");
+}
+
+#[tokio::test]
+async fn an_unsubscribed_subscriber_cannot_be_confirmed() {
+    let app = spawn_app().await;
+    let id = create_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    app.admin_api(Method::POST, &format!("/subscribers/{}/unsubscribe", id))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let response = app.admin_api(Method::POST, &format!("/subscribers/{}/confirm", id))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 409);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_removes_it_and_its_tokens() {
+    let app = spawn_app().await;
+    let id = create_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+
+    let response = app.admin_api(Method::DELETE, &format!("/subscribers/{}", id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 204);
+
+    let subscribers = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let tokens = sqlx::query!("SELECT subscription_token FROM subscription_tokens")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(subscribers.is_empty());
+    assert!(tokens.is_empty());
+    let erasure = sqlx::query!(
+        "SELECT source, recorded_by, subscriber_id FROM consent_events WHERE event_type = 'erased'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(erasure.source, "admin");
+    assert_eq!(erasure.recorded_by, Some(app.test_user.user_id));
+    assert_eq!(erasure.subscriber_id, None);
+
+    let response = app.admin_api(Method::DELETE, &format!("/subscribers/{}", id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn resending_a_confirmation_sends_a_new_working_link() {
+    let app = spawn_app().await;
+    let id = create_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app.admin_api(Method::POST, &format!("/subscribers/{}/resend_confirmation", id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn resending_a_confirmation_to_a_confirmed_subscriber_returns_a_409() {
+    let app = spawn_app().await;
+    let id = create_subscriber(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    app.admin_api(Method::POST, &format!("/subscribers/{}/confirm", id))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let response = app.admin_api(Method::POST, &format!("/subscribers/{}/resend_confirmation", id))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 409);
+}
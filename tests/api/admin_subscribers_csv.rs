@@ -0,0 +1,211 @@
+use crate::helpers::spawn_app;
+use reqwest::Method;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+
+const CSV: &str = "\
+email,name,status,tags
+ursula_le_guin@gmail.com,le guin,confirmed,sci-fi;fantasy
+tolkien@gmail.com,tolkien,,
+definitely-not-an-email,nobody,,
+herbert@gmail.com,herbert,retired,
+URSULA_LE_GUIN@gmail.com,\"Le Guin, again\",,
+";
+
+#[tokio::test]
+async fn import_persists_valid_rows_and_reports_invalid_ones() {
+    let app = spawn_app().await;
+
+    let report: serde_json::Value = app.admin_api(Method::POST, "/subscribers/import")
+        .header("Content-Type", "text/csv")
+        .body(CSV)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // The last row differs from the first only by the case of its email.
+    assert_eq!(report["imported"], 2);
+    let error_rows: Vec<_> = report["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["row"].as_u64().unwrap())
+        .collect();
+    assert_eq!(error_rows, vec![4, 5, 6]);
+
+    let saved = sqlx::query!(
+        "SELECT status, tags FROM subscriptions WHERE email = 'ursula_le_guin@gmail.com'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, "confirmed");
+    assert_eq!(saved.tags, vec!["sci-fi".to_string(), "fantasy".to_string()]);
+}
+
+#[tokio::test]
+async fn reported_rows_account_for_blank_lines() {
+    let app = spawn_app().await;
+
+    let report: serde_json::Value = app.admin_api(Method::POST, "/subscribers/import")
+        .header("Content-Type", "text/csv")
+        .body("email,name\n\nursula_le_guin@gmail.com,le guin\n\n\ndefinitely-not-an-email,nobody\n")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(report["errors"][0]["row"], 6);
+}
+
+#[tokio::test]
+async fn a_dry_run_import_persists_nothing() {
+    let app = spawn_app().await;
+
+    let report: serde_json::Value = app.admin_api(Method::POST, "/subscribers/import?dry_run=true")
+        .body(CSV)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(report["dry_run"], true);
+    assert_eq!(report["imported"], 2);
+    let saved = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_empty());
+}
+
+#[tokio::test]
+async fn a_dry_run_reports_duplicates_in_different_batches() {
+    let app = spawn_app().await;
+    let mut csv = "email,name\n".to_string();
+    for i in 0..600 {
+        csv.push_str(&format!("reader{}@gmail.com,reader\n", i));
+    }
+    csv.push_str("READER0@gmail.com,reader\n");
+
+    let report: serde_json::Value = app.admin_api(Method::POST, "/subscribers/import?dry_run=true")
+        .body(csv)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(report["imported"], 600);
+    assert_eq!(report["errors"][0]["row"], 602);
+}
+
+#[tokio::test]
+async fn import_reports_a_limited_number_of_invalid_rows() {
+    let app = spawn_app().await;
+    let csv = format!("email,name\n{}", "definitely-not-an-email,nobody\n".repeat(150));
+
+    let report: serde_json::Value = app.admin_api(Method::POST, "/subscribers/import")
+        .body(csv)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(report["errors"].as_array().unwrap().len(), 100);
+    assert_eq!(report["omitted_errors"], 50);
+}
+
+#[tokio::test]
+async fn import_queues_confirmation_emails_to_pending_subscribers_when_asked() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let report: serde_json::Value = app.admin_api(Method::POST, "/subscribers/import?send_confirmation=true")
+        .body(CSV)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // `tolkien` is pending, `le guin` was imported as already confirmed.
+    assert_eq!(report["confirmation_emails_queued"], 1);
+    assert!(app.email_server.received_requests().await.unwrap().is_empty());
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn import_without_the_required_columns_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = app.admin_api(Method::POST, "/subscribers/import")
+        .body("address,full_name\nursula_le_guin@gmail.com,le guin\n")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn export_streams_subscribers_filtered_by_status() {
+    let app = spawn_app().await;
+    app.admin_api(Method::POST, "/subscribers/import")
+        .body(CSV)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let response = app.admin_api(Method::GET, "/subscribers/export?status=confirmed")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"].to_str().unwrap().starts_with("text/csv"));
+
+    let body = response.text().await.unwrap();
+    let lines: Vec<_> = body.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], "email,name,status,tags,subscribed_at");
+    assert!(lines[1].starts_with("ursula_le_guin@gmail.com,le guin,confirmed,sci-fi;fantasy,"));
+}
+
+#[tokio::test]
+async fn export_fails_with_a_500_if_the_subscribers_cannot_be_read() {
+    let app = spawn_app().await;
+    sqlx::query!("ALTER TABLE subscriptions DROP COLUMN tags;",)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.admin_api(Method::GET, "/subscribers/export")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 500);
+}
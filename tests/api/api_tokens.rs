@@ -0,0 +1,155 @@
+use crate::helpers::{spawn_app, TestApp};
+use reqwest::Method;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+
+async fn create_token(app: &TestApp, scopes: &[&str]) -> serde_json::Value {
+    let response = app.admin_api(Method::POST, "/tokens")
+        .json(&serde_json::json!({"name": "ci", "scopes": scopes}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 201);
+    response.json().await.unwrap()
+}
+
+async fn publish_with_token(app: &TestApp, token: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .bearer_auth(token)
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .unwrap()
+}
+
+fn with_token(app: &TestApp, method: Method, path: &str, token: &str) -> reqwest::RequestBuilder {
+    reqwest::Client::new()
+        .request(method, format!("{}/admin/api{}", &app.address, path))
+        .bearer_auth(token)
+}
+
+#[tokio::test]
+async fn a_token_with_the_publish_scope_can_publish() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let created = create_token(&app, &["newsletters:publish"]).await;
+
+    let response = publish_with_token(&app, created["token"].as_str().unwrap()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_token_without_the_publish_scope_cannot_publish() {
+    let app = spawn_app().await;
+    let created = create_token(&app, &["subscribers:read"]).await;
+
+    let response = publish_with_token(&app, created["token"].as_str().unwrap()).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn unknown_tokens_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = publish_with_token(&app, "z2p_not-a-token").await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn revoked_tokens_are_rejected() {
+    let app = spawn_app().await;
+    let created = create_token(&app, &["newsletters:publish"]).await;
+
+    let response = app.admin_api(Method::DELETE, &format!("/tokens/{}", created["id"].as_str().unwrap()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 204);
+
+    let response = publish_with_token(&app, created["token"].as_str().unwrap()).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn expired_tokens_are_rejected() {
+    let app = spawn_app().await;
+    let created = create_token(&app, &["newsletters:publish"]).await;
+    sqlx::query!("UPDATE api_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = publish_with_token(&app, created["token"].as_str().unwrap()).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn only_a_hash_of_the_token_is_stored_and_listed_tokens_omit_it() {
+    let app = spawn_app().await;
+    let created = create_token(&app, &["newsletters:publish"]).await;
+    let token = created["token"].as_str().unwrap();
+
+    let stored = sqlx::query!("SELECT token_hash FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored.token_hash, token);
+
+    let listed: serde_json::Value = app.admin_api(Method::GET, "/tokens")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(listed[0]["name"], "ci");
+    assert_eq!(listed[0]["scopes"], serde_json::json!(["newsletters:publish"]));
+    assert!(listed[0].get("token").is_none());
+}
+
+#[tokio::test]
+async fn a_read_token_can_only_read_subscribers() {
+    let app = spawn_app().await;
+    let created = create_token(&app, &["subscribers:read"]).await;
+    let token = created["token"].as_str().unwrap();
+
+    let read = with_token(&app, Method::GET, "/subscribers", token).send().await.unwrap();
+    let write = with_token(&app, Method::DELETE, &format!("/subscribers/{}", uuid::Uuid::new_v4()), token)
+        .send()
+        .await
+        .unwrap();
+    let tokens = with_token(&app, Method::GET, "/tokens", token).send().await.unwrap();
+
+    assert_eq!(read.status().as_u16(), 200);
+    assert_eq!(write.status().as_u16(), 403);
+    assert_eq!(tokens.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn unsupported_scopes_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = app.admin_api(Method::POST, "/tokens")
+        .json(&serde_json::json!({"name": "ci", "scopes": ["subscribers:write"]}))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,153 @@
+use crate::helpers::{spawn_app, TestApp, TestUser};
+use reqwest::Method;
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+
+async fn store_user(app: &TestApp, role: &'static str) -> TestUser {
+    let user = TestUser::with_role(role);
+    user.store(&app.db_pool).await;
+    user
+}
+
+fn admin_api_as(app: &TestApp, user: &TestUser, method: Method, path: &str) -> reqwest::RequestBuilder {
+    reqwest::Client::new()
+        .request(method, format!("{}/admin/api{}", &app.address, path))
+        .basic_auth(&user.username, Some(&user.password))
+}
+
+async fn publish_as(app: &TestApp, user: &TestUser) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn viewers_can_read_subscribers_but_not_change_them() {
+    let app = spawn_app().await;
+    let viewer = store_user(&app, "viewer").await;
+
+    let read = admin_api_as(&app, &viewer, Method::GET, "/subscribers").send().await.unwrap();
+    let write = admin_api_as(&app, &viewer, Method::DELETE, &format!("/subscribers/{}", Uuid::new_v4()))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(read.status().as_u16(), 200);
+    assert_eq!(write.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn viewers_cannot_publish_newsletters() {
+    let app = spawn_app().await;
+    let viewer = store_user(&app, "viewer").await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = publish_as(&app, &viewer).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn editors_can_publish_newsletters_but_not_manage_users() {
+    let app = spawn_app().await;
+    let editor = store_user(&app, "editor").await;
+
+    let publish = publish_as(&app, &editor).await;
+    let locked = admin_api_as(&app, &editor, Method::GET, "/users/locked").send().await.unwrap();
+
+    assert_eq!(publish.status().as_u16(), 200);
+    assert_eq!(locked.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn forbidden_is_distinguished_from_unauthenticated() {
+    let app = spawn_app().await;
+    let viewer = store_user(&app, "viewer").await;
+
+    let forbidden = admin_api_as(&app, &viewer, Method::GET, "/users/locked").send().await.unwrap();
+    let anonymous = reqwest::Client::new()
+        .get(format!("{}/admin/api/users/locked", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(forbidden.status().as_u16(), 403);
+    assert!(forbidden.headers().get("WWW-Authenticate").is_none());
+    assert_eq!(anonymous.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn admins_can_change_roles_and_it_applies_immediately() {
+    let app = spawn_app().await;
+    let user = store_user(&app, "viewer").await;
+
+    let before = publish_as(&app, &user).await;
+    let response = app.admin_api(Method::PUT, &format!("/users/{}/role", user.user_id))
+        .json(&serde_json::json!({"role": "editor"}))
+        .send()
+        .await
+        .unwrap();
+    let after = publish_as(&app, &user).await;
+
+    assert_eq!(before.status().as_u16(), 403);
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(after.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn unknown_roles_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    let user = store_user(&app, "viewer").await;
+
+    let response = app.admin_api(Method::PUT, &format!("/users/{}/role", user.user_id))
+        .json(&serde_json::json!({"role": "owner"}))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn admins_cannot_change_their_own_role() {
+    let app = spawn_app().await;
+
+    let response = app.admin_api(Method::PUT, &format!("/users/{}/role", app.test_user.user_id))
+        .json(&serde_json::json!({"role": "viewer"}))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn tokens_cannot_carry_scopes_beyond_the_role_of_their_owner() {
+    let app = spawn_app().await;
+    let viewer = store_user(&app, "viewer").await;
+
+    let response = admin_api_as(&app, &viewer, Method::POST, "/tokens")
+        .json(&serde_json::json!({"name": "ci", "scopes": ["newsletters:publish"]}))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 403);
+}
@@ -0,0 +1,237 @@
+use crate::helpers::spawn_app;
+use reqwest::Method;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+
+#[tokio::test]
+async fn subscribing_records_who_consented_to_what() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("User-Agent", "consent-test-agent")
+        .header("X-Forwarded-For", "203.0.113.7")
+        .form(&[
+            ("name", "le guin"),
+            ("email", "ursula_le_guin@gmail.com"),
+            ("source", "blog_footer"),
+        ])
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!(
+        "SELECT event_type, ip_address, user_agent, source, consent_text_version FROM consent_events"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.event_type, "subscribed");
+    // Without trusted proxies, forwarding headers are not taken at their word.
+    assert_eq!(saved.ip_address.as_deref(), Some("127.0.0.1"));
+    assert_eq!(saved.user_agent.as_deref(), Some("consent-test-agent"));
+    assert_eq!(saved.source, "blog_footer");
+    assert_eq!(saved.consent_text_version, "2023-01-10");
+}
+
+#[tokio::test]
+async fn the_consent_text_version_cannot_be_chosen_by_the_client() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&consent_text_version=v7".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!("SELECT source, consent_text_version FROM consent_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.source, "subscribe_form");
+    assert_eq!(saved.consent_text_version, "2023-01-10");
+}
+
+#[tokio::test]
+async fn confirming_records_a_second_event_for_the_same_consent_text() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!(
+        "SELECT event_type, source, consent_text_version FROM consent_events ORDER BY occurred_at"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.len(), 2);
+    assert_eq!(saved[1].event_type, "confirmed");
+    assert_eq!(saved[1].source, "confirmation_link");
+    assert_eq!(saved[1].consent_text_version, "2023-01-10");
+}
+
+#[tokio::test]
+async fn confirming_through_the_admin_api_records_a_consent_event() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    app.admin_api(Method::POST, &format!("/subscribers/{}/confirm", subscriber_id))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!(
+        "SELECT event_type, source, ip_address, user_agent, recorded_by FROM consent_events ORDER BY occurred_at"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.len(), 2);
+    assert_eq!(saved[1].event_type, "confirmed");
+    assert_eq!(saved[1].source, "admin");
+    // The admin's address and user agent are no evidence of the subscriber's consent.
+    assert_eq!(saved[1].ip_address, None);
+    assert_eq!(saved[1].user_agent, None);
+    assert_eq!(saved[1].recorded_by, Some(app.test_user.user_id));
+}
+
+#[tokio::test]
+async fn imported_subscribers_get_consent_events() {
+    let app = spawn_app().await;
+
+    app.admin_api(Method::POST, "/subscribers/import")
+        .header("Content-Type", "text/csv")
+        .body("email,name,status\nursula_le_guin@gmail.com,le guin,confirmed\ntolkien@gmail.com,tolkien,\n")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!(
+        r#"
+        SELECT s.email, e.event_type, e.source, e.consent_text_version, e.ip_address, e.recorded_by
+        FROM consent_events e JOIN subscriptions s ON s.id = e.subscriber_id
+        ORDER BY s.email, e.occurred_at
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let events: Vec<_> = saved
+        .iter()
+        .map(|e| (e.email.as_str(), e.event_type.as_str(), e.source.as_str()))
+        .collect();
+    assert_eq!(events, vec![
+        ("tolkien@gmail.com", "subscribed", "csv_import"),
+        ("ursula_le_guin@gmail.com", "subscribed", "csv_import"),
+        ("ursula_le_guin@gmail.com", "confirmed", "csv_import"),
+    ]);
+    assert!(saved.iter().all(|e| e.consent_text_version == "2023-01-10"));
+    assert!(saved.iter().all(|e| e.ip_address.is_none() && e.recorded_by == Some(app.test_user.user_id)));
+}
+
+#[tokio::test]
+async fn consent_events_cannot_be_rewritten() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let outcome = sqlx::query!("UPDATE consent_events SET consent_text_version = 'forged'")
+        .execute(&app.db_pool)
+        .await;
+
+    assert!(outcome.is_err());
+}
+
+#[tokio::test]
+async fn overly_long_consent_fields_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    let body = format!(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&source={}",
+        "a".repeat(101)
+    );
+
+    let response = app.post_subscriptions(body).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn consent_events_are_exposed_through_the_admin_api() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    let events: serde_json::Value = app.admin_api(Method::GET, &format!("/subscribers/{}/consent_events", subscriber_id))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(events.as_array().unwrap().len(), 1);
+    assert_eq!(events[0]["event_type"], "subscribed");
+}
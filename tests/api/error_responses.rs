@@ -0,0 +1,56 @@
+use crate::helpers::spawn_app;
+
+
+#[actix_rt::test]
+async fn api_clients_get_problem_details_with_every_invalid_field() {
+    let app = spawn_app().await;
+
+    let response = app.post_subscriptions("name=&email=definitely-not-an-email".into()).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(response.headers()["Content-Type"], "application/problem+json");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "about:blank");
+    assert_eq!(body["title"], "Bad Request");
+    assert_eq!(body["status"], 400);
+    assert_eq!(body["code"], "validation_error");
+    let fields: Vec<&str> = body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, vec!["name", "email"]);
+}
+
+#[actix_rt::test]
+async fn browsers_get_an_error_page() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8")
+        .body("name=le%20guin&email=%3Cscript%3E")
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(response.headers()["Content-Type"].to_str().unwrap().starts_with("text/html"));
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<h1>Bad Request</h1>"));
+    assert!(html.contains("<strong>email</strong>"));
+    assert!(!html.contains("<script>"));
+}
+
+#[actix_rt::test]
+async fn malformed_requests_get_problem_details() {
+    let app = spawn_app().await;
+
+    let response = app.post_subscriptions("name=le%20guin".into()).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "malformed_request");
+}
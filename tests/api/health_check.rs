@@ -14,4 +14,57 @@ async fn health_check_works() {
 
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
-}
\ No newline at end of file
+}
+
+#[actix_rt::test]
+async fn liveness_works() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/health/live", &app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    assert!(response.status().is_success());
+}
+
+#[actix_rt::test]
+async fn readiness_reports_each_component() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/health/ready", &app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "up");
+    assert_eq!(body["components"]["database"]["status"], "up");
+    assert_eq!(body["components"]["migrations"]["status"], "up");
+    assert!(body["components"]["database"]["latency_ms"].is_u64());
+    assert!(body["components"].get("redis").is_none());
+}
+
+#[actix_rt::test]
+async fn readiness_fails_while_migrations_are_pending() {
+    let app = spawn_app().await;
+    sqlx::query("DELETE FROM _sqlx_migrations WHERE version = (SELECT max(version) FROM _sqlx_migrations)")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/health/ready", &app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "down");
+    assert_eq!(body["components"]["database"]["status"], "up");
+    assert_eq!(body["components"]["migrations"]["status"], "down");
+}
@@ -1,16 +1,14 @@
-use std::net::TcpListener;
-use zero2prod::startup::run;
 use sqlx::{PgConnection, PgPool, Connection, Executor};
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use zero2prod::configuration::{get_configuration, DatabaseSettings, Settings};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 use uuid::Uuid;
 use argon2::password_hash::SaltString;
-use argon2::{Argon2, PasswordHasher};
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
-use secrecy::ExposeSecret;
 use wiremock::MockServer;
 use zero2prod::email_client::EmailClient;
-use zero2prod::startup::Application;
+use zero2prod::startup::{migrate_database, Application};
+use zero2prod::worker::deliver_queued_confirmation_emails;
 
 
 static TRACING: Lazy<()> = Lazy::new(|| {
@@ -33,6 +31,8 @@ pub struct TestApp {
     pub email_server: MockServer,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
+    pub email_client: EmailClient,
+    pub base_url: String,
 }
 
 pub struct ConfirmationLinks {
@@ -44,32 +44,45 @@ pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
     pub password: String,
+    pub role: &'static str,
 }
 
 impl TestUser {
     pub fn generate() -> Self {
+        Self::with_role("admin")
+    }
+
+    pub fn with_role(role: &'static str) -> Self {
         Self {
             user_id: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
             password: Uuid::new_v4().to_string(),
+            role,
         }
     }
 
-    async fn store(&self, pool: &PgPool) {
+    pub async fn store(&self, pool: &PgPool) {
         let salt = SaltString::generate(&mut rand::thread_rng());
-        let password_hash = Argon2::default()
+        // Match the parameters in `base.yaml`, so that logging in does not
+        // upgrade the hash.
+        let password_hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(15000, 2, 1, None).unwrap(),
+        )
             .hash_password(self.password.as_bytes(), &salt)
             .unwrap()
             .to_string();
 
         sqlx::query!(
             r#"
-            INSERT INTO users (user_id, username, password_hash)
-            VALUES ($1, $2, $3)
+            INSERT INTO users (user_id, username, password_hash, role)
+            VALUES ($1, $2, $3, $4)
             "#,
             self.user_id,
             self.username,
             password_hash,
+            self.role,
         )
         .execute(pool)
         .await
@@ -88,9 +101,19 @@ impl TestApp {
             .expect("failed to execute request")
     }
 
+    pub async fn post_data_request(&self, body: String) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/data_request", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/newsletters", &self.address))
+            .post(format!("{}/newsletters", &self.address))
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
             .json(&body)
             .send()
@@ -98,6 +121,19 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    /// Sends the queued emails, as the worker would.
+    pub async fn dispatch_all_pending_emails(&self) {
+        deliver_queued_confirmation_emails(&self.db_pool, &self.email_client, &self.base_url)
+            .await
+            .unwrap();
+    }
+
+    pub fn admin_api(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        reqwest::Client::new()
+            .request(method, format!("{}/admin/api{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
@@ -114,8 +150,8 @@ impl TestApp {
             confirmation_link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
 
         ConfirmationLinks {
             plain_text,
@@ -128,7 +164,7 @@ impl TestApp {
             Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
             .form(body)
             .send()
             .await
@@ -137,7 +173,7 @@ impl TestApp {
 
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -148,26 +184,35 @@ impl TestApp {
 }
 
 async fn configure_database(config: &DatabaseSettings) -> PgPool {
-    let _connection = PgConnection::connect(&config.connection_string_wo_db().expose_secret())
+    let connection_pool = create_database(config).await;
+
+    migrate_database(&connection_pool)
+        .await
+        .expect("failed to migrate the database");
+
+    connection_pool
+}
+
+/// Creates an empty database, without migrations.
+pub async fn create_database(config: &DatabaseSettings) -> PgPool {
+    let _connection = PgConnection::connect_with(&config.without_db())
         .await
         .expect("failed to connect to postgres")
         .execute(format!(r#"CREATE DATABASE "{}";"#, config.database_name).as_str())
         .await
         .expect("failed to create database");
 
-    let connection_pool = PgPool::connect(&config.connection_string().expose_secret())
+    PgPool::connect_with(config.with_db())
         .await
-        .expect("failed to connect to postgres");
-
-    sqlx::migrate!("./migrations")
-        .run(&connection_pool)
-        .await
-        .expect("failed to migrate the database");
-
-    connection_pool
+        .expect("failed to connect to postgres")
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Like `spawn_app`, with a hook to adjust the configuration before the application is built.
+pub async fn spawn_app_with(customize: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     let email_server = MockServer::start().await;
@@ -177,10 +222,15 @@ pub async fn spawn_app() -> TestApp {
         config.database.database_name = Uuid::new_v4().to_string(); // different db for each test
         config.application.port = 0; // random OS port
         config.email_client.base_url = email_server.uri();
+        customize(&mut config);
         config
     };
     let db_pool = configure_database(&config.database).await; // for test purposes
-    let application = Application::build(config).expect("failed to build application");
+    let email_client = config.email_client.clone().client().unwrap();
+    let base_url = config.application.base_url.clone();
+    let application = Application::build(config)
+        .await
+        .expect("failed to build application");
     let port = application.port(); // actually assigned port by OS
     let address = format!("http://127.0.0.1:{}", port);
     let test_user = TestUser::generate();
@@ -191,9 +241,10 @@ pub async fn spawn_app() -> TestApp {
         .build()
         .unwrap();
 
+    #[allow(clippy::let_underscore_future)]
     let _ = tokio::spawn(application.run_until_stopped());
 
-    TestApp { address, port, db_pool, email_server, test_user, api_client }
+    TestApp { address, port, db_pool, email_server, test_user, api_client, email_client, base_url }
 }
 
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
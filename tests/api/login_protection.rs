@@ -0,0 +1,153 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp, TestUser};
+use reqwest::Method;
+
+
+async fn login(app: &TestApp, user: &TestUser, password: &str) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &user.username,
+        "password": password,
+    }))
+    .await
+}
+
+fn newsletter_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn an_account_is_locked_after_too_many_failed_attempts() {
+    let app = spawn_app_with(|config| config.login_protection.max_failed_attempts = 3).await;
+    let user = TestUser::generate();
+    user.store(&app.db_pool).await;
+
+    for _ in 0..3 {
+        let response = login(&app, &user, "wrong-password").await;
+        assert_is_redirect_to(&response, "/login");
+    }
+    // Even the right password is turned away while the account is locked.
+    let response = login(&app, &user, &user.password).await;
+
+    assert_is_redirect_to(&response, "/login");
+    let flash_cookie = response.cookies().find(|c| c.name() == "_flash").unwrap();
+    assert_eq!(flash_cookie.value(), "Authentication failed");
+}
+
+#[tokio::test]
+async fn a_locked_account_cannot_be_told_apart_from_an_unknown_username() {
+    let app = spawn_app_with(|config| config.login_protection.max_failed_attempts = 1).await;
+    let user = TestUser::generate();
+    user.store(&app.db_pool).await;
+    login(&app, &user, "wrong-password").await;
+
+    let locked = login(&app, &user, "wrong-password").await;
+    let unknown = login(&app, &TestUser::generate(), "wrong-password").await;
+
+    for response in [locked, unknown] {
+        assert_is_redirect_to(&response, "/login");
+        let flash_cookie = response.cookies().find(|c| c.name() == "_flash").unwrap();
+        assert_eq!(flash_cookie.value(), "Authentication failed");
+    }
+}
+
+#[tokio::test]
+async fn basic_authentication_is_locked_out_as_well() {
+    let app = spawn_app_with(|config| config.login_protection.max_failed_attempts = 2).await;
+
+    for _ in 0..2 {
+        let response = reqwest::Client::new()
+            .post(format!("{}/newsletters", &app.address))
+            .basic_auth(&app.test_user.username, Some("wrong-password"))
+            .json(&newsletter_body())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    let response = app.post_newsletters(newsletter_body()).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_successful_login_resets_the_failure_count() {
+    let app = spawn_app_with(|config| config.login_protection.max_failed_attempts = 2).await;
+    let user = TestUser::generate();
+    user.store(&app.db_pool).await;
+
+    login(&app, &user, "wrong-password").await;
+    assert_is_redirect_to(&login(&app, &user, &user.password).await, "/");
+    login(&app, &user, "wrong-password").await;
+
+    assert_is_redirect_to(&login(&app, &user, &user.password).await, "/");
+}
+
+#[tokio::test]
+async fn an_address_is_blocked_after_too_many_failures_across_usernames() {
+    let app = spawn_app_with(|config| config.login_protection.max_failed_per_ip = 2).await;
+
+    for i in 0..2 {
+        let response = app.post_login(&serde_json::json!({
+            "username": format!("random-username-{}", i),
+            "password": "random-password",
+        }))
+        .await;
+        let flash_cookie = response.cookies().find(|c| c.name() == "_flash").unwrap();
+        assert_eq!(flash_cookie.value(), "Authentication failed");
+    }
+    let response = login(&app, &app.test_user, &app.test_user.password).await;
+
+    let flash_cookie = response.cookies().find(|c| c.name() == "_flash").unwrap();
+    assert_eq!(flash_cookie.value(), "Too many failed attempts, try again later");
+}
+
+#[tokio::test]
+async fn admins_can_list_and_unlock_locked_accounts() {
+    let app = spawn_app_with(|config| config.login_protection.max_failed_attempts = 1).await;
+    let user = TestUser::generate();
+    user.store(&app.db_pool).await;
+    login(&app, &user, "wrong-password").await;
+
+    let locked: serde_json::Value = app.admin_api(Method::GET, "/users/locked")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(locked.as_array().unwrap().len(), 1);
+    assert_eq!(locked[0]["username"], user.username.as_str());
+
+    let response = app.admin_api(Method::POST, &format!("/users/{}/unlock", user.user_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 204);
+
+    assert_is_redirect_to(&login(&app, &user, &user.password).await, "/");
+    let locked: serde_json::Value = app.admin_api(Method::GET, "/users/locked")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(locked.as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn unlocking_an_unknown_user_returns_a_404() {
+    let app = spawn_app().await;
+
+    let response = app.admin_api(Method::POST, &format!("/users/{}/unlock", uuid::Uuid::new_v4()))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 404);
+}
@@ -3,4 +3,21 @@ mod health_check;
 mod subscriptions;
 mod subscriptions_confirm;
 mod newsletters;
-mod login;
\ No newline at end of file
+mod login;
+mod admin_subscribers;
+mod admin_subscribers_csv;
+mod subscriber_data;
+mod consent_events;
+mod subscribe_protection;
+mod login_protection;
+mod two_factor;
+mod api_tokens;
+mod authorization;
+mod user_management;
+mod user_bootstrap;
+mod password_hashing;
+mod worker;
+mod migrations;
+mod metrics;
+mod request_id;
+mod error_responses;
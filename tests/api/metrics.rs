@@ -0,0 +1,31 @@
+use crate::helpers::spawn_app;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+
+#[actix_rt::test]
+async fn metrics_cover_requests_emails_and_the_subscription_funnel() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into()).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/metrics", &app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response.text().await.unwrap();
+    // Metrics are shared by every application in the test binary, so only
+    // their presence is checked.
+    assert!(body.contains(r#"http_requests_total{method="POST",route="/subscriptions",status="200"}"#));
+    assert!(body.contains("http_request_duration_seconds_bucket{"));
+    assert!(body.contains(r#"emails_total{kind="confirmation",outcome="sent"}"#));
+    assert!(body.contains(r#"subscription_events_total{event="subscribed"}"#));
+    assert!(body.contains(r#"db_pool_connections{state="idle"}"#));
+}
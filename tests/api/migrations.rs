@@ -0,0 +1,23 @@
+use crate::helpers::create_database;
+use uuid::Uuid;
+use zero2prod::configuration::get_configuration;
+use zero2prod::startup::{migrate_database, MIGRATOR};
+
+
+#[tokio::test]
+async fn instances_starting_at_the_same_time_migrate_the_database_once() {
+    let mut configuration = get_configuration().unwrap();
+    configuration.database.database_name = Uuid::new_v4().to_string();
+    let pool = create_database(&configuration.database).await;
+
+    let (first, second) = tokio::join!(migrate_database(&pool), migrate_database(&pool));
+
+    first.unwrap();
+    second.unwrap();
+    let applied = sqlx::query!(r#"SELECT count(*) AS "count!" FROM _sqlx_migrations"#)
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(applied as usize, MIGRATOR.iter().count());
+}
@@ -0,0 +1,78 @@
+use crate::helpers::{spawn_app, TestApp};
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
+
+
+/// Replaces the hash of the test user with one computed like before
+/// parameters were configurable.
+async fn store_outdated_hash(app: &TestApp) -> String {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(Algorithm::Argon2i, Version::V0x13, Params::new(4096, 3, 1, None).unwrap())
+        .hash_password(app.test_user.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+    sqlx::query!(
+        "UPDATE users SET password_hash = $2 WHERE user_id = $1",
+        app.test_user.user_id,
+        password_hash,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    password_hash
+}
+
+async fn stored_hash(app: &TestApp) -> String {
+    sqlx::query!("SELECT password_hash FROM users WHERE user_id = $1", app.test_user.user_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .password_hash
+}
+
+async fn login(app: &TestApp, password: &str) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": password,
+    }))
+    .await
+}
+
+#[tokio::test]
+async fn outdated_hashes_are_upgraded_on_a_successful_login() {
+    let app = spawn_app().await;
+    store_outdated_hash(&app).await;
+
+    let response = login(&app, &app.test_user.password).await;
+
+    assert_eq!(response.status().as_u16(), 303);
+    assert!(stored_hash(&app).await.starts_with("$argon2id$v=19$m=15000,t=2,p=1$"));
+    // The upgraded hash still matches the same password.
+    let again = reqwest::Client::new()
+        .get(format!("{}/admin/api/subscribers", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(again.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn outdated_hashes_are_kept_after_a_failed_login() {
+    let app = spawn_app().await;
+    let outdated = store_outdated_hash(&app).await;
+
+    login(&app, "wrong-password").await;
+
+    assert_eq!(stored_hash(&app).await, outdated);
+}
+
+#[tokio::test]
+async fn current_hashes_are_left_alone() {
+    let app = spawn_app().await;
+    let before = stored_hash(&app).await;
+
+    login(&app, &app.test_user.password).await;
+
+    assert_eq!(stored_hash(&app).await, before);
+}
@@ -0,0 +1,78 @@
+use crate::helpers::spawn_app;
+
+
+#[actix_rt::test]
+async fn every_response_carries_a_request_id() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/health/live", &app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    let request_id = response.headers().get("X-Request-Id").expect("no X-Request-Id header");
+    assert!(!request_id.is_empty());
+}
+
+#[actix_rt::test]
+async fn an_incoming_request_id_is_echoed() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/health/live", &app.address))
+        .header("X-Request-Id", "upstream-request-42")
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    assert_eq!(response.headers()["X-Request-Id"], "upstream-request-42");
+}
+
+#[actix_rt::test]
+async fn a_malformed_incoming_request_id_is_replaced() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/health/live", &app.address))
+        .header("X-Request-Id", "not a valid id")
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    assert_ne!(response.headers()["X-Request-Id"], "not a valid id");
+}
+
+#[actix_rt::test]
+async fn errors_have_a_json_body_with_a_code_and_the_request_id() {
+    let app = spawn_app().await;
+
+    let response = app.post_subscriptions("name=le%20guin&email=definitely-not-an-email".into()).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let request_id = response.headers()["X-Request-Id"].to_str().unwrap().to_string();
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "validation_error");
+    assert!(body["detail"].is_string());
+    assert_eq!(body["request_id"], request_id.as_str());
+}
+
+#[actix_rt::test]
+async fn authentication_errors_keep_their_challenge() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "Newsletter body as plain text", "html": "<p>Newsletter body as HTML</p>" }
+        }))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(response.headers()["WWW-Authenticate"], r#"Basic realm="publish""#);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "authentication_failed");
+}
@@ -0,0 +1,131 @@
+use crate::helpers::{spawn_app, spawn_app_with};
+use secrecy::Secret;
+use wiremock::matchers::{any, body_string_contains, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::configuration::ChallengeSettings;
+
+
+#[tokio::test]
+async fn subscribing_from_one_address_is_rate_limited() {
+    let app = spawn_app_with(|config| config.subscribe_protection.max_per_ip = 2).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    for i in 0..2 {
+        let response = app.post_subscriptions(format!("name=le%20guin&email=ursula{}%40gmail.com", i)).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    let response = app.post_subscriptions("name=le%20guin&email=ursula2%40gmail.com".into()).await;
+
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn subscribing_the_same_email_is_rate_limited() {
+    let app = spawn_app_with(|config| config.subscribe_protection.max_per_email = 1).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let first = app.post_subscriptions(body.into()).await;
+    let second = app.post_subscriptions(body.replace("ursula", "URSULA")).await;
+
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn filling_in_the_honeypot_stores_nothing_and_sends_no_email() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&website=spam.example".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_empty());
+}
+
+async fn fake_challenge_provider(success: bool) -> MockServer {
+    let provider = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(body_string_contains("secret=challenge-secret"))
+        .and(body_string_contains("response=challenge-token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({"success": success})))
+        .expect(1)
+        .mount(&provider)
+        .await;
+    provider
+}
+
+fn challenge_settings(provider: &MockServer) -> ChallengeSettings {
+    ChallengeSettings {
+        verify_url: provider.uri(),
+        secret_key: Secret::new("challenge-secret".to_string()),
+    }
+}
+
+#[tokio::test]
+async fn a_passed_challenge_lets_the_subscription_through() {
+    let provider = fake_challenge_provider(true).await;
+    let app = spawn_app_with(|config| {
+        config.subscribe_protection.challenge = Some(challenge_settings(&provider));
+    }).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&challenge_response=challenge-token".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_failed_challenge_is_rejected_with_a_403() {
+    let provider = fake_challenge_provider(false).await;
+    let app = spawn_app_with(|config| {
+        config.subscribe_protection.challenge = Some(challenge_settings(&provider));
+    }).await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&challenge_response=challenge-token".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn a_missing_challenge_response_is_rejected_with_a_403() {
+    let provider = MockServer::start().await;
+    let app = spawn_app_with(|config| {
+        config.subscribe_protection.challenge = Some(challenge_settings(&provider));
+    }).await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
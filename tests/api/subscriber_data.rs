@@ -0,0 +1,216 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+
+struct DataRequestLinks {
+    export: reqwest::Url,
+    erasure: reqwest::Url,
+}
+
+async fn create_subscriber(app: &TestApp) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+async fn request_data_links(app: &TestApp) -> DataRequestLinks {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_data_request("email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let links: Vec<_> = linkify::LinkFinder::new()
+        .links(body["TextBody"].as_str().unwrap())
+        .filter(|l| *l.kind() == linkify::LinkKind::Url)
+        .map(|l| {
+            let mut link = reqwest::Url::parse(l.as_str()).unwrap();
+            assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+            link.set_port(Some(app.port)).unwrap();
+            link
+        })
+        .collect();
+    assert_eq!(links.len(), 2);
+
+    DataRequestLinks {
+        export: links[0].clone(),
+        erasure: links[1].clone(),
+    }
+}
+
+#[tokio::test]
+async fn data_requests_for_unknown_addresses_send_no_email() {
+    let app = spawn_app().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_data_request("email=nobody%40gmail.com".into()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn data_requests_from_one_address_are_rate_limited() {
+    let app = spawn_app_with(|config| config.subscribe_protection.max_per_ip = 2).await;
+
+    for i in 0..2 {
+        let response = app.post_data_request(format!("email=nobody{}%40gmail.com", i)).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    let response = app.post_data_request("email=nobody2%40gmail.com".into()).await;
+
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn data_requests_for_the_same_email_are_rate_limited() {
+    let app = spawn_app_with(|config| config.subscribe_protection.max_per_email = 1).await;
+    create_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let first = app.post_data_request("email=ursula_le_guin%40gmail.com".into()).await;
+    let second = app.post_data_request("email=URSULA_LE_GUIN%40gmail.com".into()).await;
+
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn the_export_link_returns_everything_we_hold() {
+    let app = spawn_app().await;
+    create_subscriber(&app).await;
+    let links = request_data_links(&app).await;
+
+    let response = reqwest::get(links.export).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let export: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(export["subscription"]["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(export["subscription"]["status"], "pending_confirmation");
+    assert_eq!(export["subscription_tokens"].as_array().unwrap().len(), 1);
+    assert_eq!(export["consent_events"][0]["event_type"], "subscribed");
+}
+
+#[tokio::test]
+async fn a_tampered_link_is_rejected_with_a_401() {
+    let app = spawn_app().await;
+    create_subscriber(&app).await;
+    let links = request_data_links(&app).await;
+
+    let mut tampered = links.export.clone();
+    let query: Vec<(String, String)> = tampered
+        .query_pairs()
+        .map(|(k, v)| match k.as_ref() {
+            "expires" => (k.to_string(), (v.parse::<i64>().unwrap() + 3600).to_string()),
+            _ => (k.to_string(), v.to_string()),
+        })
+        .collect();
+    tampered.query_pairs_mut().clear().extend_pairs(query);
+
+    let response = reqwest::get(tampered).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn an_export_link_cannot_be_used_to_erase() {
+    let app = spawn_app().await;
+    create_subscriber(&app).await;
+    let links = request_data_links(&app).await;
+
+    let params: Vec<(String, String)> = links.export
+        .query_pairs()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions/data/erase", app.address))
+        .form(&params)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn confirming_the_erasure_deletes_every_row_about_the_subscriber_but_anonymised_consent() {
+    let app = spawn_app().await;
+    create_subscriber(&app).await;
+    let links = request_data_links(&app).await;
+
+    let page = reqwest::get(links.erasure.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(page.contains(r#"<form action="/subscriptions/data/erase" method="post">"#));
+
+    let params: Vec<(String, String)> = links.erasure
+        .query_pairs()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions/data/erase", app.address))
+        .form(&params)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let subscribers = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let tokens = sqlx::query!("SELECT subscription_token FROM subscription_tokens")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let consent_events = sqlx::query!(
+        "SELECT event_type, subscriber_id, subscriber_hash, ip_address, user_agent FROM consent_events ORDER BY occurred_at"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(subscribers.is_empty());
+    assert!(tokens.is_empty());
+    // The proof of consent and of its withdrawal outlives the subscriber,
+    // without naming them.
+    let event_types: Vec<_> = consent_events.iter().map(|e| e.event_type.as_str()).collect();
+    assert_eq!(event_types, vec!["subscribed", "erased"]);
+    assert!(consent_events.iter().all(|e| e.subscriber_id.is_none()
+        && e.ip_address.is_none()
+        && e.user_agent.is_none()
+        && e.subscriber_hash == consent_events[0].subscriber_hash));
+    assert!(consent_events[0].subscriber_hash.is_some());
+
+    let response = reqwest::get(links.export).await.unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+}
@@ -1,4 +1,4 @@
-use crate::helpers::{TestApp, spawn_app};
+use crate::helpers::spawn_app;
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
 
@@ -30,7 +30,7 @@ async fn subscribe_persists_the_new_subscriber() {
         .expect(1)
         .mount(&app.email_server)
         .await;
-    let response = app.post_subscriptions(body.into()).await;
+    let _response = app.post_subscriptions(body.into()).await;
 
     let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
         .fetch_one(&app.db_pool)
@@ -108,7 +108,7 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     let app = spawn_app().await;
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
 
-    sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN subscription_token;",)
+    sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN subscription_token CASCADE;",)
         .execute(&app.db_pool)
         .await
         .unwrap(); // sabotage
@@ -117,3 +117,51 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
 
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[actix_rt::test]
+async fn subscribe_normalizes_the_email_address() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=%20Ursula%40Gmail.COM%20".into()).await;
+
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to fetch saved subscriptions");
+    assert_eq!(saved.email, "Ursula@gmail.com");
+}
+
+#[actix_rt::test]
+async fn subscribe_does_not_store_an_address_twice_in_different_cases() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula%40gmail.com".into()).await;
+    app.post_subscriptions("name=le%20guin&email=URSULA%40GMAIL.COM".into()).await;
+
+    let count = sqlx::query_scalar!("SELECT COUNT(*) FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to count subscriptions");
+    assert_eq!(count, Some(1));
+}
+
+#[actix_rt::test]
+async fn subscribe_rejects_blocked_email_domains() {
+    let app = spawn_app().await;
+
+    let response = app.post_subscriptions("name=le%20guin&email=ursula%40eu.mailinator.com".into()).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "email");
+}
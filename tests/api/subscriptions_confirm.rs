@@ -1,4 +1,3 @@
-use reqwest::Url;
 use wiremock::{ResponseTemplate, Mock};
 use wiremock::matchers::{path, method};
 use crate::helpers::spawn_app;
@@ -56,7 +55,7 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
 
     assert_eq!(confirmation_links.html.host_str().unwrap(), "127.0.0.1"); // make sure we don't call random APIs on the web
 
-    let response = reqwest::get(confirmation_links.html)
+    let _response = reqwest::get(confirmation_links.html)
         .await
         .unwrap()
         .error_for_status()
@@ -70,4 +69,44 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     assert_eq!(saved.email, "ursula_le_guin@gmail.com");
     assert_eq!(saved.name, "le guin");
     assert_eq!(saved.status, "confirmed");
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn clicking_an_old_confirmation_link_does_not_resubscribe() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(confirmation_links.html)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+    let events = sqlx::query!(r#"SELECT count(*) AS "count!" FROM consent_events WHERE event_type = 'confirmed'"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.count, 1);
+}
@@ -0,0 +1,234 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use secrecy::Secret;
+use zero2prod::authentication::totp;
+
+
+struct Enrolment {
+    secret: Secret<String>,
+    confirmation_code: String,
+    recovery_codes: Vec<String>,
+}
+
+fn code_for_step(secret: &Secret<String>, offset: i64) -> String {
+    let now = chrono::Utc::now().timestamp() as u64;
+    let step = (totp::step_at(now) as i64 + offset) as u64;
+    totp::code_at_step(secret, step).unwrap()
+}
+
+async fn log_in(app: &TestApp) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    }))
+    .await
+}
+
+async fn post_form(app: &TestApp, path: &str, form: &[(&str, &str)]) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}{}", &app.address, path))
+        .form(form)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get(app: &TestApp, path: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}{}", &app.address, path))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// Logs in with the password and enables two-factor authentication.
+async fn enrol(app: &TestApp) -> Enrolment {
+    assert_is_redirect_to(&log_in(app).await, "/");
+
+    let page = post_form(app, "/account/two_factor/enrol", &[]).await.text().await.unwrap();
+    let secret = page
+        .split("secret=")
+        .nth(1)
+        .unwrap()
+        .split('&')
+        .next()
+        .unwrap()
+        .to_string();
+    let secret = Secret::new(secret);
+
+    let confirmation_code = code_for_step(&secret, 0);
+    let response = post_form(app, "/account/two_factor/confirm", &[("code", &confirmation_code)]).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let page = response.text().await.unwrap();
+    let recovery_codes: Vec<String> = page
+        .split("<li><code>")
+        .skip(1)
+        .map(|s| s.split("</code>").next().unwrap().to_string())
+        .collect();
+    assert_eq!(recovery_codes.len(), 10);
+
+    Enrolment { secret, confirmation_code, recovery_codes }
+}
+
+#[tokio::test]
+async fn account_pages_require_a_login() {
+    let app = spawn_app().await;
+
+    let response = get(&app, "/account/two_factor").await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_wrong_code_does_not_enable_two_factor_authentication() {
+    let app = spawn_app().await;
+    log_in(&app).await;
+    post_form(&app, "/account/two_factor/enrol", &[]).await;
+
+    let response = post_form(&app, "/account/two_factor/confirm", &[("code", "000000")]).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let enabled = sqlx::query!("SELECT totp_enabled FROM users")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .totp_enabled;
+    assert!(!enabled);
+}
+
+#[tokio::test]
+async fn a_password_alone_does_not_log_in_an_enrolled_user() {
+    let app = spawn_app().await;
+    enrol(&app).await;
+    // A fresh client, so that the session from the enrolment is gone.
+    let app = TestApp { api_client: reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap(), ..app };
+
+    let response = log_in(&app).await;
+
+    assert_is_redirect_to(&response, "/login/two_factor");
+    assert_is_redirect_to(&get(&app, "/account/two_factor").await, "/login");
+}
+
+#[tokio::test]
+async fn the_code_from_the_app_completes_the_login() {
+    let app = spawn_app().await;
+    let enrolment = enrol(&app).await;
+    log_in(&app).await;
+
+    let response = post_form(&app, "/login/two_factor", &[("code", &code_for_step(&enrolment.secret, 1))]).await;
+
+    assert_is_redirect_to(&response, "/");
+    assert_eq!(get(&app, "/account/two_factor").await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_code_cannot_be_used_twice() {
+    let app = spawn_app().await;
+    let enrolment = enrol(&app).await;
+    log_in(&app).await;
+
+    let response = post_form(&app, "/login/two_factor", &[("code", &enrolment.confirmation_code)]).await;
+
+    assert_is_redirect_to(&response, "/login/two_factor");
+    let html_page = get(&app, "/login/two_factor").await.text().await.unwrap();
+    assert!(html_page.contains("<p><i>Invalid code</i></p>"));
+}
+
+#[tokio::test]
+async fn recovery_codes_work_once() {
+    let app = spawn_app().await;
+    let enrolment = enrol(&app).await;
+    let recovery_code = &enrolment.recovery_codes[0];
+
+    log_in(&app).await;
+    let response = post_form(&app, "/login/two_factor", &[("code", recovery_code)]).await;
+    assert_is_redirect_to(&response, "/");
+
+    log_in(&app).await;
+    let response = post_form(&app, "/login/two_factor", &[("code", recovery_code)]).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+}
+
+#[tokio::test]
+async fn too_many_wrong_codes_require_the_password_again() {
+    let app = spawn_app().await;
+    enrol(&app).await;
+    log_in(&app).await;
+
+    for _ in 0..4 {
+        let response = post_form(&app, "/login/two_factor", &[("code", "000000")]).await;
+        assert_is_redirect_to(&response, "/login/two_factor");
+    }
+    let response = post_form(&app, "/login/two_factor", &[("code", "000000")]).await;
+
+    assert_is_redirect_to(&response, "/login");
+    assert_is_redirect_to(&get(&app, "/login/two_factor").await, "/login");
+}
+
+#[tokio::test]
+async fn replaying_the_pending_session_does_not_reset_the_wrong_codes() {
+    let app = spawn_app().await;
+    let enrolment = enrol(&app).await;
+    let pending_session = log_in(&app)
+        .await
+        .cookies()
+        .find(|c| c.name() == "id")
+        .unwrap()
+        .value()
+        .to_string();
+    // Every attempt sends the session cookie as it was right after the
+    // password check, so nothing the server wrote into it afterwards counts.
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let post_code = |code: String| client
+        .post(format!("{}/login/two_factor", &app.address))
+        .header("Cookie", format!("id={}", pending_session))
+        .form(&[("code", code)])
+        .send();
+
+    for _ in 0..5 {
+        post_code("000000".into()).await.unwrap();
+    }
+    let response = post_code(code_for_step(&enrolment.secret, 1)).await.unwrap();
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn basic_auth_publishing_is_disabled_for_enrolled_users() {
+    let app = spawn_app().await;
+    enrol(&app).await;
+
+    let response = app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn two_factor_authentication_can_be_disabled_with_a_recovery_code() {
+    let app = spawn_app().await;
+    let enrolment = enrol(&app).await;
+    log_in(&app).await;
+    post_form(&app, "/login/two_factor", &[("code", &enrolment.recovery_codes[0])]).await;
+
+    let response = post_form(&app, "/account/two_factor/disable", &[("code", &enrolment.recovery_codes[1])]).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let recovery_codes = sqlx::query!("SELECT id FROM recovery_codes")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(recovery_codes.is_empty());
+}
@@ -0,0 +1,86 @@
+use crate::helpers::spawn_app;
+use claim::{assert_none, assert_some};
+use secrecy::Secret;
+use zero2prod::authentication::{create_initial_admin, create_user, CreateUserError, PasswordHashing, Role};
+use zero2prod::configuration::get_configuration;
+
+
+fn password() -> Secret<String> {
+    Secret::new("a-long-enough-password".to_string())
+}
+
+fn password_hashing() -> PasswordHashing {
+    PasswordHashing::new(&get_configuration().unwrap().password_hashing).unwrap()
+}
+
+#[tokio::test]
+async fn created_users_can_authenticate_with_their_role() {
+    let app = spawn_app().await;
+
+    create_user("ursula", password(), Role::Viewer, &password_hashing(), &app.db_pool).await.unwrap();
+
+    let read = reqwest::Client::new()
+        .get(format!("{}/admin/api/subscribers", &app.address))
+        .basic_auth("ursula", Some("a-long-enough-password"))
+        .send()
+        .await
+        .unwrap();
+    let manage = reqwest::Client::new()
+        .get(format!("{}/admin/api/users", &app.address))
+        .basic_auth("ursula", Some("a-long-enough-password"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(read.status().as_u16(), 200);
+    assert_eq!(manage.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn creating_a_user_with_a_taken_username_fails() {
+    let app = spawn_app().await;
+
+    let outcome = create_user(&app.test_user.username, password(), Role::Admin, &password_hashing(), &app.db_pool)
+        .await;
+
+    assert!(matches!(outcome, Err(CreateUserError::UsernameTaken(_))));
+}
+
+#[tokio::test]
+async fn weak_passwords_are_rejected() {
+    let app = spawn_app().await;
+
+    let outcome = create_user("ursula", Secret::new("short".into()), Role::Admin, &password_hashing(), &app.db_pool)
+        .await;
+
+    assert!(matches!(outcome, Err(CreateUserError::ValidationError(_))));
+}
+
+#[tokio::test]
+async fn the_initial_admin_is_only_created_on_the_first_run() {
+    let app = spawn_app().await;
+    sqlx::query!("DELETE FROM users").execute(&app.db_pool).await.unwrap();
+
+    let first = create_initial_admin("root", password(), &password_hashing(), &app.db_pool).await.unwrap();
+    let second = create_initial_admin("root", password(), &password_hashing(), &app.db_pool).await.unwrap();
+    let other = create_initial_admin("another-root", password(), &password_hashing(), &app.db_pool).await.unwrap();
+
+    assert_some!(first);
+    assert_none!(second);
+    assert_none!(other);
+    let admins = sqlx::query!("SELECT username, role FROM users")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(admins.len(), 1);
+    assert_eq!(admins[0].username, "root");
+    assert_eq!(admins[0].role, "admin");
+}
+
+#[tokio::test]
+async fn no_initial_admin_is_created_when_users_exist() {
+    let app = spawn_app().await;
+
+    let created = create_initial_admin("root", password(), &password_hashing(), &app.db_pool).await.unwrap();
+
+    assert_none!(created);
+}
@@ -0,0 +1,324 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp, TestUser};
+use reqwest::Method;
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+
+async fn invite(app: &TestApp, email: &str, role: &str) -> reqwest::Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app.admin_api(Method::POST, "/users/invitations")
+        .json(&serde_json::json!({"email": email, "role": role}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 201);
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    app.get_confirmation_links(&email_request).plain_text
+}
+
+/// Submits the form behind an invitation link, the query of which carries
+/// the signed parameters.
+async fn accept(app: &TestApp, link: &reqwest::Url, username: &str, password: &str) -> reqwest::Response {
+    let mut form: Vec<(String, String)> = link.query_pairs().into_owned().collect();
+    form.push(("username".into(), username.into()));
+    form.push(("password".into(), password.into()));
+    form.push(("password_check".into(), password.into()));
+    reqwest::Client::new()
+        .post(format!("{}/invitations/accept", &app.address))
+        .form(&form)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn request_reset_link(app: &TestApp, email: &str) -> reqwest::Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    reqwest::Client::new()
+        .post(format!("{}/password_reset", &app.address))
+        .form(&[("email", email)])
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    app.get_confirmation_links(&email_request).plain_text
+}
+
+async fn reset_password(app: &TestApp, link: &reqwest::Url, password: &str) -> reqwest::Response {
+    let token = link.query_pairs().find(|(k, _)| k == "token").unwrap().1.into_owned();
+    reqwest::Client::new()
+        .post(format!("{}/password_reset/confirm", &app.address))
+        .form(&[("token", token.as_str()), ("password", password), ("password_check", password)])
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn subscribers_as(app: &TestApp, username: &str, password: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/admin/api/subscribers", &app.address))
+        .basic_auth(username, Some(password))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn set_email(app: &TestApp, user: &TestUser, email: &str) {
+    sqlx::query!("UPDATE users SET email = $2 WHERE user_id = $1", user.user_id, email)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn an_invited_user_can_create_their_account_with_the_invited_role() {
+    let app = spawn_app().await;
+    let link = invite(&app, "ursula@example.com", "editor").await;
+
+    let form = reqwest::get(link.clone()).await.unwrap();
+    assert_eq!(form.status().as_u16(), 200);
+    let response = accept(&app, &link, "ursula", "a-long-enough-password").await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let user = sqlx::query!("SELECT email, role FROM users WHERE username = 'ursula'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(user.email.as_deref(), Some("ursula@example.com"));
+    assert_eq!(user.role, "editor");
+    assert_eq!(subscribers_as(&app, "ursula", "a-long-enough-password").await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn an_invitation_can_only_be_accepted_once() {
+    let app = spawn_app().await;
+    let link = invite(&app, "ursula@example.com", "viewer").await;
+
+    accept(&app, &link, "ursula", "a-long-enough-password").await.error_for_status().unwrap();
+    let response = accept(&app, &link, "another-ursula", "a-long-enough-password").await;
+
+    assert_eq!(response.status().as_u16(), 410);
+}
+
+#[tokio::test]
+async fn a_tampered_invitation_link_is_rejected() {
+    let app = spawn_app().await;
+    let mut link = invite(&app, "ursula@example.com", "viewer").await;
+    let tampered: Vec<(String, String)> = link
+        .query_pairs()
+        .into_owned()
+        .map(|(k, v)| if k == "invitation_id" { (k, Uuid::new_v4().to_string()) } else { (k, v) })
+        .collect();
+    link.query_pairs_mut().clear().extend_pairs(tampered);
+
+    let response = accept(&app, &link, "ursula", "a-long-enough-password").await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn short_passwords_are_rejected_when_accepting_an_invitation() {
+    let app = spawn_app().await;
+    let link = invite(&app, "ursula@example.com", "viewer").await;
+
+    let response = accept(&app, &link, "ursula", "short").await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn users_are_listed_with_their_role() {
+    let app = spawn_app().await;
+
+    let users: serde_json::Value = app.admin_api(Method::GET, "/users")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(users[0]["username"], app.test_user.username.as_str());
+    assert_eq!(users[0]["role"], "admin");
+}
+
+#[tokio::test]
+async fn disabled_users_cannot_authenticate_until_enabled_again() {
+    let app = spawn_app().await;
+    let user = TestUser::with_role("viewer");
+    user.store(&app.db_pool).await;
+
+    let disable = app.admin_api(Method::POST, &format!("/users/{}/disable", user.user_id))
+        .send()
+        .await
+        .unwrap();
+    let while_disabled = subscribers_as(&app, &user.username, &user.password).await;
+    app.admin_api(Method::POST, &format!("/users/{}/enable", user.user_id))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let after_enabling = subscribers_as(&app, &user.username, &user.password).await;
+
+    assert_eq!(disable.status().as_u16(), 204);
+    assert_eq!(while_disabled.status().as_u16(), 401);
+    assert_eq!(after_enabling.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn deleted_users_are_gone_with_their_tokens() {
+    let app = spawn_app().await;
+    let user = TestUser::with_role("viewer");
+    user.store(&app.db_pool).await;
+    reqwest::Client::new()
+        .post(format!("{}/admin/api/tokens", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(&serde_json::json!({"name": "ci", "scopes": ["subscribers:read"]}))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let first = app.admin_api(Method::DELETE, &format!("/users/{}", user.user_id)).send().await.unwrap();
+    let second = app.admin_api(Method::DELETE, &format!("/users/{}", user.user_id)).send().await.unwrap();
+
+    assert_eq!(first.status().as_u16(), 204);
+    assert_eq!(second.status().as_u16(), 404);
+    let tokens = sqlx::query!("SELECT count(*) AS \"count!\" FROM api_tokens WHERE user_id = $1", user.user_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.count, 0);
+}
+
+#[tokio::test]
+async fn admins_cannot_delete_or_disable_themselves() {
+    let app = spawn_app().await;
+    let path = format!("/users/{}", app.test_user.user_id);
+
+    let delete = app.admin_api(Method::DELETE, &path).send().await.unwrap();
+    let disable = app.admin_api(Method::POST, &format!("{}/disable", path)).send().await.unwrap();
+
+    assert_eq!(delete.status().as_u16(), 409);
+    assert_eq!(disable.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn a_password_can_be_reset_once_via_the_emailed_link() {
+    let app = spawn_app().await;
+    set_email(&app, &app.test_user, "admin@example.com").await;
+    let link = request_reset_link(&app, "admin@example.com").await;
+
+    let form = reqwest::get(link.clone()).await.unwrap();
+    assert_eq!(form.status().as_u16(), 200);
+    let response = reset_password(&app, &link, "a-brand-new-password").await;
+    let reused = reset_password(&app, &link, "yet-another-password").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(reused.status().as_u16(), 401);
+    let username = &app.test_user.username;
+    assert_eq!(subscribers_as(&app, username, &app.test_user.password).await.status().as_u16(), 401);
+    assert_eq!(subscribers_as(&app, username, "a-brand-new-password").await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_password_reset_link_is_sent_whatever_the_case_of_the_email() {
+    let app = spawn_app().await;
+    set_email(&app, &app.test_user, "admin@example.com").await;
+
+    let link = request_reset_link(&app, "Admin@Example.COM").await;
+
+    let response = reset_password(&app, &link, "a-brand-new-password").await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn inviting_an_existing_user_with_a_differently_cased_email_returns_a_409() {
+    let app = spawn_app().await;
+    set_email(&app, &app.test_user, "admin@example.com").await;
+
+    let response = app.admin_api(Method::POST, "/users/invitations")
+        .json(&serde_json::json!({"email": "ADMIN@example.com", "role": "viewer"}))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn requesting_a_reset_for_an_unknown_email_looks_the_same_and_sends_nothing() {
+    let app = spawn_app().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/password_reset", &app.address))
+        .form(&[("email", "nobody@example.com")])
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn password_reset_requests_from_one_address_are_rate_limited() {
+    let app = spawn_app_with(|config| config.subscribe_protection.max_per_ip = 2).await;
+    let post_reset_request = |email: String| reqwest::Client::new()
+        .post(format!("{}/password_reset", &app.address))
+        .form(&[("email", email)])
+        .send();
+
+    for i in 0..2 {
+        let response = post_reset_request(format!("nobody{}@example.com", i)).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    let response = post_reset_request("nobody2@example.com".into()).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn password_reset_requests_for_the_same_email_are_rate_limited() {
+    let app = spawn_app_with(|config| config.subscribe_protection.max_per_email = 1).await;
+    set_email(&app, &app.test_user, "ursula@example.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let post_reset_request = |email: &'static str| reqwest::Client::new()
+        .post(format!("{}/password_reset", &app.address))
+        .form(&[("email", email)])
+        .send();
+
+    let first = post_reset_request("ursula@example.com").await.unwrap();
+    let second = post_reset_request("ursula@example.com").await.unwrap();
+
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 429);
+}
@@ -0,0 +1,128 @@
+use crate::helpers::{spawn_app, TestApp};
+use chrono::{Duration, Utc};
+use reqwest::Method;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::worker::purge_expired_records;
+
+
+#[tokio::test]
+async fn expired_reset_tokens_and_invitations_are_purged() {
+    let app = spawn_app().await;
+    let now = Utc::now();
+    for (token_hash, expires_at) in [("expired", now - Duration::hours(1)), ("valid", now + Duration::hours(1))] {
+        sqlx::query!(
+            "INSERT INTO password_reset_tokens (token_hash, user_id, created_at, expires_at) VALUES ($1, $2, $3, $4)",
+            token_hash,
+            app.test_user.user_id,
+            now - Duration::hours(2),
+            expires_at,
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+    for expires_at in [now - Duration::hours(1), now + Duration::hours(1)] {
+        sqlx::query!(
+            r#"
+            INSERT INTO user_invitations (id, email, role, created_at, expires_at)
+            VALUES ($1, 'invitee@example.com', 'viewer', $2, $3)
+            "#,
+            Uuid::new_v4(),
+            now - Duration::hours(2),
+            expires_at,
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+
+    let purged = purge_expired_records(&app.db_pool).await.unwrap();
+
+    assert_eq!(purged, 2);
+    let token = sqlx::query!("SELECT token_hash FROM password_reset_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(token.token_hash, "valid");
+    let invitation = sqlx::query!("SELECT expires_at FROM user_invitations")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(invitation.expires_at > now);
+}
+
+async fn import_pending_subscriber(app: &TestApp) {
+    app.admin_api(Method::POST, "/subscribers/import?send_confirmation=true")
+        .body("email,name\nursula_le_guin@gmail.com,le guin\n")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn queued_confirmation_emails_are_sent_once() {
+    let app = spawn_app().await;
+    import_pending_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.dispatch_all_pending_emails().await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_failed_confirmation_email_is_retried_later() {
+    let app = spawn_app().await;
+    import_pending_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.dispatch_all_pending_emails().await;
+
+    let queued = sqlx::query!("SELECT n_retries, execute_after FROM confirmation_email_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.n_retries, 1);
+    assert!(queued.execute_after > Utc::now());
+}
+
+#[tokio::test]
+async fn no_confirmation_email_is_sent_to_a_subscriber_who_already_confirmed() {
+    let app = spawn_app().await;
+    import_pending_subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'confirmed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.dispatch_all_pending_emails().await;
+
+    let queued = sqlx::query!("SELECT subscriber_id FROM confirmation_email_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(queued.is_empty());
+}